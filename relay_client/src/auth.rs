@@ -0,0 +1,199 @@
+use {
+    crate::error::BoxError,
+    chrono::{DateTime, Utc},
+    relay_rpc::{
+        auth::{ed25519_dalek::SigningKey, AuthToken, SerializedAuthToken},
+        jwt::{JwtBasicClaims, VerifyableClaims},
+    },
+    std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+};
+
+/// Default time before the token expiration at which a new token is minted.
+/// Accounts for the clock skew between the client and the Relay.
+pub const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Source of the auth tokens used to authorize the Relay connections and
+/// requests.
+///
+/// The provider is called before each websocket (re)connection and each HTTP
+/// request (see [`ConnectionOptions::with_auth_provider()`]), allowing the
+/// long-lived clients to keep using valid tokens. Implemented for closures
+/// returning [`SerializedAuthToken`].
+///
+/// [`ConnectionOptions::with_auth_provider()`]: crate::ConnectionOptions::with_auth_provider
+pub trait AuthTokenProvider: Send + Sync + 'static {
+    /// Returns a valid auth token.
+    fn token(&self) -> Result<SerializedAuthToken, BoxError>;
+}
+
+impl<F> AuthTokenProvider for F
+where
+    F: Fn() -> Result<SerializedAuthToken, BoxError> + Send + Sync + 'static,
+{
+    fn token(&self) -> Result<SerializedAuthToken, BoxError> {
+        self()
+    }
+}
+
+/// Shared [`AuthTokenProvider`] handle.
+#[derive(Clone)]
+pub struct AuthProvider(Arc<dyn AuthTokenProvider>);
+
+impl AuthProvider {
+    pub fn new(provider: impl AuthTokenProvider) -> Self {
+        Self(Arc::new(provider))
+    }
+
+    pub fn token(&self) -> Result<SerializedAuthToken, BoxError> {
+        self.0.token()
+    }
+}
+
+impl std::fmt::Debug for AuthProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("AuthProvider").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+struct CachedToken {
+    token: SerializedAuthToken,
+    exp: Option<DateTime<Utc>>,
+}
+
+/// [`AuthTokenProvider`] minting the tokens from an [`AuthToken`] template
+/// using the client's [`SigningKey`].
+///
+/// The minted token is reused until it's about to expire, i.e. the remaining
+/// lifetime is less than the refresh margin. Tokens without the TTL never
+/// expire and are minted only once.
+///
+/// The template should not have the `iat` set, so that each token is issued at
+/// the time it's minted.
+#[derive(Debug)]
+pub struct SigningKeyAuthProvider {
+    key: SigningKey,
+    template: AuthToken,
+    refresh_margin: Duration,
+    cached: Mutex<Option<CachedToken>>,
+}
+
+impl SigningKeyAuthProvider {
+    pub fn new(key: SigningKey, template: AuthToken) -> Self {
+        Self {
+            key,
+            template,
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub fn with_refresh_margin(mut self, margin: Duration) -> Self {
+        self.refresh_margin = margin;
+        self
+    }
+
+    fn mint(&self) -> Result<CachedToken, BoxError> {
+        let token = self.template.as_jwt(&self.key)?;
+        let claims = JwtBasicClaims::try_from_str(&token.to_string())?;
+        let exp = claims
+            .exp
+            .map(|exp| DateTime::from_timestamp(exp, 0).ok_or("Invalid token expiration"))
+            .transpose()?;
+
+        Ok(CachedToken { token, exp })
+    }
+
+    fn needs_refresh(&self, cached: &CachedToken) -> bool {
+        let Some(exp) = cached.exp else {
+            return false;
+        };
+
+        let margin =
+            chrono::Duration::from_std(self.refresh_margin).unwrap_or(chrono::Duration::MAX);
+
+        exp.checked_sub_signed(margin)
+            .is_none_or(|refresh_at| Utc::now() >= refresh_at)
+    }
+}
+
+impl AuthTokenProvider for SigningKeyAuthProvider {
+    fn token(&self) -> Result<SerializedAuthToken, BoxError> {
+        let mut cached = crate::lock(&self.cached);
+
+        if let Some(cached) = &*cached {
+            if !self.needs_refresh(cached) {
+                return Ok(cached.token.clone());
+            }
+        }
+
+        let minted = self.mint()?;
+        let token = minted.token.clone();
+        *cached = Some(minted);
+
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(margin: Duration) -> SigningKeyAuthProvider {
+        let key = SigningKey::generate(&mut rand::thread_rng());
+        let template = AuthToken::new("http://example.com").ttl(Duration::from_secs(3600));
+
+        SigningKeyAuthProvider::new(key, template).with_refresh_margin(margin)
+    }
+
+    fn set_cached(provider: &SigningKeyAuthProvider, token: &str, expires_in: i64) {
+        *provider.cached.lock().unwrap() = Some(CachedToken {
+            token: serde_json::from_value(token.into()).unwrap(),
+            exp: Some(Utc::now() + chrono::Duration::seconds(expires_in)),
+        });
+    }
+
+    #[test]
+    fn reuses_valid_token() {
+        let provider = provider(DEFAULT_REFRESH_MARGIN);
+
+        let first = provider.token().unwrap().to_string();
+        let second = provider.token().unwrap().to_string();
+        assert_eq!(first, second);
+
+        let exp = JwtBasicClaims::try_from_str(&first).unwrap().exp;
+        assert!(exp.is_some());
+    }
+
+    #[test]
+    fn refreshes_expiring_token() {
+        let provider = provider(Duration::from_secs(60));
+
+        // Outside of the refresh margin.
+        set_cached(&provider, "cached", 120);
+        assert_eq!(provider.token().unwrap().to_string(), "cached");
+
+        // Within the refresh margin.
+        set_cached(&provider, "cached", 30);
+        assert_ne!(provider.token().unwrap().to_string(), "cached");
+
+        // Already expired.
+        set_cached(&provider, "cached", -30);
+        assert_ne!(provider.token().unwrap().to_string(), "cached");
+    }
+
+    #[test]
+    fn closure_provider() {
+        let key = SigningKey::generate(&mut rand::thread_rng());
+        let provider = AuthProvider::new(move || {
+            AuthToken::new("http://example.com")
+                .as_jwt(&key)
+                .map_err(Into::into)
+        });
+
+        assert!(provider.token().is_ok());
+    }
+}
@@ -0,0 +1,103 @@
+use {rand::Rng, std::time::Duration};
+
+/// Jittered exponential backoff.
+///
+/// The delay before attempt `n` (starting from `1`) is `initial_delay *
+/// multiplier^(n - 1)`, capped at `max_delay`. The resulting value is then
+/// reduced by a random amount of up to `jitter` (a fraction in the `0.0..=1.0`
+/// range) to avoid synchronized retries from multiple clients.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    /// Delay before the first attempt.
+    pub initial_delay: Duration,
+
+    /// Upper bound for the delay.
+    pub max_delay: Duration,
+
+    /// Factor by which the delay grows after each attempt.
+    pub multiplier: f64,
+
+    /// Fraction of the delay that is randomized.
+    pub jitter: f64,
+}
+
+impl Backoff {
+    pub fn new(initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            max_delay,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Returns the delay before the specified attempt. Attempts are counted
+    /// starting from `1`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let max = self.max_delay.as_secs_f64();
+        let delay =
+            (self.initial_delay.as_secs_f64() * self.multiplier.max(1.0).powi(exp)).min(max);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+
+        let delay = if jitter > 0.0 {
+            delay * (1.0 - jitter * rand::thread_rng().gen::<f64>())
+        } else {
+            delay
+        };
+
+        Duration::from_secs_f64(delay.max(0.0))
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.5,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_growth() {
+        let backoff =
+            Backoff::new(Duration::from_millis(100), Duration::from_secs(1)).with_jitter(0.0);
+
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(4), Duration::from_millis(800));
+        assert_eq!(backoff.delay(5), Duration::from_secs(1));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_bounds() {
+        let backoff =
+            Backoff::new(Duration::from_millis(100), Duration::from_secs(1)).with_jitter(0.5);
+
+        for attempt in 1..10 {
+            let delay = backoff.delay(attempt);
+            let max =
+                Duration::from_millis(100 * 2u64.pow(attempt - 1)).min(Duration::from_secs(1));
+
+            assert!(delay <= max);
+            assert!(delay >= max / 2);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use {
+    crate::ClientError,
+    futures_util::{stream, StreamExt, TryStreamExt},
+    relay_rpc::rpc::FetchResponse,
+    std::future::Future,
+};
+
+/// Default number of chunks of an oversized batch request that are sent
+/// concurrently.
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 4;
+
+/// Splits the batch items into chunks of up to `chunk_size` items.
+///
+/// Always yields at least one chunk, so that an empty batch is still sent and
+/// rejected with the same validation error as before.
+pub(crate) fn chunks<T>(items: Vec<T>, chunk_size: usize) -> impl Iterator<Item = Vec<T>> {
+    let mut items = items.into_iter().peekable();
+    let mut first = true;
+
+    std::iter::from_fn(move || {
+        if !first && items.peek().is_none() {
+            return None;
+        }
+
+        first = false;
+
+        Some(items.by_ref().take(chunk_size.max(1)).collect())
+    })
+}
+
+/// Sends the chunk requests with at most `concurrency` of them in flight,
+/// returning the responses in the order of the chunks.
+///
+/// The requests are created lazily, as the previous ones complete. Fails with
+/// the first error, discarding the remaining requests.
+pub(crate) async fn send_chunks<I, F, R, E>(requests: I, concurrency: usize) -> Result<Vec<R>, E>
+where
+    I: IntoIterator<Item = F>,
+    F: Future<Output = Result<R, E>>,
+{
+    stream::iter(requests)
+        .buffered(concurrency.max(1))
+        .try_collect()
+        .await
+}
+
+/// Joins the responses of the requests for the [`chunks()`] of `len` items,
+/// each carrying one item per item of its request.
+///
+/// Fails with [`ClientError::InvalidBatchResponse`] if a response doesn't match
+/// the size of its chunk, since its items can't be matched with the items of
+/// the request.
+pub(crate) fn join_chunks<T>(
+    responses: Vec<Vec<T>>,
+    len: usize,
+    chunk_size: usize,
+) -> Result<Vec<T>, ClientError> {
+    let mut remaining = len;
+    let mut joined = Vec::with_capacity(len);
+
+    for response in responses {
+        let expected = remaining.min(chunk_size.max(1));
+
+        if response.len() != expected {
+            return Err(ClientError::InvalidBatchResponse {
+                expected,
+                actual: response.len(),
+            });
+        }
+
+        remaining -= expected;
+        joined.extend(response);
+    }
+
+    Ok(joined)
+}
+
+/// Merges the responses of the chunked `irn_batchFetchMessages` requests.
+pub(crate) fn merge_fetch_responses(responses: Vec<FetchResponse>) -> FetchResponse {
+    responses.into_iter().fold(
+        FetchResponse {
+            messages: Vec::new(),
+            has_more: false,
+        },
+        |mut merged, response| {
+            merged.messages.extend(response.messages);
+            merged.has_more |= response.has_more;
+            merged
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{
+            sync::atomic::{AtomicUsize, Ordering},
+            time::Duration,
+        },
+    };
+
+    #[test]
+    fn chunks_preserve_order() {
+        let split = |items: Vec<u8>| chunks(items, 3).collect::<Vec<_>>();
+
+        assert_eq!(split((0..7).collect()), vec![
+            vec![0, 1, 2],
+            vec![3, 4, 5],
+            vec![6]
+        ]);
+        assert_eq!(split((0..6).collect()), vec![vec![0, 1, 2], vec![3, 4, 5]]);
+        assert_eq!(split(Vec::new()), vec![Vec::<u8>::new()]);
+    }
+
+    #[test]
+    fn join_chunk_responses() {
+        let joined = join_chunks(vec![vec![0, 1, 2], vec![3, 4, 5], vec![6]], 7, 3);
+        assert_eq!(joined.unwrap(), (0..7).collect::<Vec<_>>());
+
+        // A short response of a chunk would shift the items of the following
+        // chunks.
+        let joined = join_chunks(vec![vec![0, 1], vec![3, 4, 5], vec![6]], 7, 3);
+        assert!(matches!(
+            joined,
+            Err(ClientError::InvalidBatchResponse {
+                expected: 3,
+                actual: 2
+            })
+        ));
+
+        let joined = join_chunks(vec![vec![0, 1, 2], vec![3, 4, 5], vec![6, 7]], 7, 3);
+        assert!(matches!(
+            joined,
+            Err(ClientError::InvalidBatchResponse {
+                expected: 1,
+                actual: 2
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn bounded_concurrency() {
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+
+        let requests = (0..10).map(|idx| {
+            let in_flight = &in_flight;
+            let max_in_flight = &max_in_flight;
+
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+
+                // Complete out of order.
+                tokio::time::sleep(Duration::from_millis(10 - idx)).await;
+
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok::<_, ()>(idx)
+            }
+        });
+
+        let responses = send_chunks(requests, 3).await.unwrap();
+
+        assert_eq!(responses, (0..10).collect::<Vec<_>>());
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,7 +1,103 @@
-use relay_rpc::rpc::{self, error::ServiceError};
+use {
+    relay_rpc::{
+        domain::Topic,
+        rpc::{self, error::ServiceError},
+    },
+    std::sync::Arc,
+};
 
 pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
+/// Coarse classification of the client errors. See [`Error::kind()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Connection or transport failure, e.g. a dropped websocket connection or
+    /// a failed HTTP request.
+    Transport,
+
+    /// The response didn't arrive in time.
+    Timeout,
+
+    /// The request exceeded the rate limits of either the Relay or the
+    /// client-side rate limiter.
+    RateLimited,
+
+    /// Authentication or authorization failure.
+    Auth,
+
+    /// The request was rejected as malformed.
+    InvalidPayload,
+
+    /// The request was rejected by the RPC method handler, e.g. due to an
+    /// invalid TTL.
+    Handler,
+
+    /// Relay-side failure, such as an internal error, an HTTP 5xx status or a
+    /// malformed response.
+    Server,
+
+    /// Client-side failure, such as a closed channel or a full request queue.
+    Client,
+}
+
+impl ErrorKind {
+    /// Returns `true` if the error is caused by the request itself, and
+    /// repeating it unchanged would fail again.
+    pub fn is_client_fault(&self) -> bool {
+        matches!(self, Self::Auth | Self::InvalidPayload | Self::Handler)
+    }
+
+    /// Returns `true` if the error is caused by a Relay-side failure.
+    pub fn is_server_fault(&self) -> bool {
+        matches!(self, Self::Server)
+    }
+
+    /// Returns the kind of the Relay error response with the specified code.
+    pub fn from_code(code: i32) -> Self {
+        match code {
+            rpc::CODE_AUTH => Self::Auth,
+            rpc::CODE_TOO_MANY_REQUESTS => Self::RateLimited,
+            rpc::CODE_PAYLOAD => Self::InvalidPayload,
+            rpc::CODE_HANDLER => Self::Handler,
+            _ => Self::Server,
+        }
+    }
+}
+
+impl<T: ServiceError> From<&rpc::Error<T>> for ErrorKind {
+    fn from(err: &rpc::Error<T>) -> Self {
+        Self::from_code(err.code())
+    }
+}
+
+/// Implements the predicates classifying the error by its [`ErrorKind`], for
+/// the types providing the `kind()` method.
+macro_rules! impl_kind_predicates {
+    ([$($generics:tt)*] $ty:ty) => {
+        impl<$($generics)*> $ty {
+            pub fn is_auth_failure(&self) -> bool {
+                self.kind() == $crate::error::ErrorKind::Auth
+            }
+
+            pub fn is_payload_invalid(&self) -> bool {
+                self.kind() == $crate::error::ErrorKind::InvalidPayload
+            }
+
+            /// Returns `true` for the connection and transport failures that
+            /// are not caused by the request itself.
+            pub fn is_transient_transport(&self) -> bool {
+                self.kind() == $crate::error::ErrorKind::Transport
+            }
+        }
+    };
+
+    ($ty:ty) => {
+        impl_kind_predicates!([] $ty);
+    };
+}
+
+pub(crate) use impl_kind_predicates;
+
 /// Errors generated while parsing
 /// [`ConnectionOptions`][crate::ConnectionOptions] and creating an HTTP request
 /// for the websocket connection.
@@ -13,6 +109,9 @@ pub enum RequestBuildError {
     #[error("Failed to add request headers")]
     Headers,
 
+    #[error("Failed to obtain auth token: {0}")]
+    AuthToken(BoxError),
+
     #[error("Failed to parse connection URL: {0}")]
     Url(#[from] url::ParseError),
 
@@ -44,6 +143,9 @@ pub enum ClientError {
     #[error("Invalid response ID")]
     InvalidResponseId,
 
+    #[error("Invalid batch response: expected {expected} items, got {actual}")]
+    InvalidBatchResponse { expected: usize, actual: usize },
+
     #[error("Invalid error response")]
     InvalidErrorResponse,
 
@@ -62,8 +164,99 @@ pub enum ClientError {
 
     #[error("Invalid request type")]
     InvalidRequestType,
+
+    #[error("Request timed out")]
+    RequestTimeout,
+
+    #[error("Request queue is full")]
+    QueueFull,
+
+    #[error("Message expired before it could be sent")]
+    MessageExpired,
+
+    #[error("Subscription store error: {0}")]
+    SubscriptionStore(BoxError),
+
+    #[error("Client-side rate limit exceeded")]
+    RateLimited,
+
+    #[error("Client is shutting down")]
+    ShuttingDown,
+
+    /// The [`SubscriptionStream`] of the topic is full, and the inbound message
+    /// has been rejected, so that the Relay redelivers it.
+    ///
+    /// [`SubscriptionStream`]: crate::websocket::SubscriptionStream
+    #[error("Subscription stream of topic {0} is full, message rejected")]
+    SubscriptionStreamFull(Topic),
+
+    /// Failure of the batch request the request has been coalesced into,
+    /// shared by all of the coalesced requests.
+    #[error("Batch request failed: {0}")]
+    BatchFailed(Arc<ClientError>),
 }
 
+impl RequestBuildError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::AuthToken(_) => ErrorKind::Auth,
+            Self::WebsocketClient(err) => err.kind(),
+            Self::HttpClient(err) => err.kind(),
+            Self::Query(_) | Self::Headers | Self::Url(_) => ErrorKind::Client,
+        }
+    }
+}
+
+impl ClientError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::RequestBuilder(err) => err.kind(),
+            Self::BatchFailed(err) => err.kind(),
+            Self::WebsocketClient(err) => err.kind(),
+            Self::HttpClient(err) => err.kind(),
+            Self::Rpc { code, .. } => ErrorKind::from_code(*code),
+            Self::RequestTimeout => ErrorKind::Timeout,
+            Self::RateLimited => ErrorKind::RateLimited,
+
+            Self::InvalidResponseId
+            | Self::InvalidBatchResponse { .. }
+            | Self::InvalidErrorResponse
+            | Self::Deserialization(_) => ErrorKind::Server,
+
+            Self::ChannelClosed
+            | Self::DuplicateRequestId
+            | Self::Serialization(_)
+            | Self::InvalidRequestType
+            | Self::QueueFull
+            | Self::MessageExpired
+            | Self::SubscriptionStore(_)
+            | Self::ShuttingDown
+            | Self::SubscriptionStreamFull(_) => ErrorKind::Client,
+        }
+    }
+
+    /// Returns `true` if the request is likely to succeed if repeated later.
+    ///
+    /// The client-side [`ClientError::RateLimited`] errors are not considered
+    /// retryable, since they're only returned in the
+    /// [`RateLimitMode::FailFast`] mode.
+    ///
+    /// [`RateLimitMode::FailFast`]: crate::rate_limit::RateLimitMode::FailFast
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::WebsocketClient(err) => err.is_retryable(),
+            Self::HttpClient(err) => err.is_retryable(),
+            Self::BatchFailed(err) => err.is_retryable(),
+            Self::RequestTimeout => true,
+
+            Self::Rpc { code, data, .. } => rpc::is_retryable(*code, data.as_deref()),
+            _ => false,
+        }
+    }
+}
+
+impl_kind_predicates!(ClientError);
+
 impl From<rpc::ErrorData> for ClientError {
     fn from(err: rpc::ErrorData) -> Self {
         Self::Rpc {
@@ -80,11 +273,32 @@ pub enum Error<T> {
     #[error(transparent)]
     Client(ClientError),
 
-    /// Error response received from the relay.
+    /// Error response received from the relay. The errors this version of the
+    /// client doesn't recognize are preserved as [`rpc::Error::Unknown`].
     #[error(transparent)]
     Response(#[from] rpc::Error<T>),
 }
 
+impl<T: ServiceError> Error<T> {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Client(err) => err.kind(),
+            Self::Response(err) => err.into(),
+        }
+    }
+
+    /// Returns `true` if the request is likely to succeed if repeated later.
+    /// See [`ClientError::is_retryable()`].
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Client(err) => err.is_retryable(),
+            Self::Response(err) => err.is_retryable(),
+        }
+    }
+}
+
+impl_kind_predicates!([T: ServiceError] Error<T>);
+
 impl<T: ServiceError> From<ClientError> for Error<T> {
     fn from(err: ClientError) -> Self {
         match err {
@@ -99,14 +313,121 @@ impl<T: ServiceError> From<ClientError> for Error<T> {
                     data,
                 };
 
-                match rpc::Error::try_from(err) {
-                    Ok(err) => Error::Response(err),
+                Error::Response(rpc::Error::from_error_data(err))
+            }
+
+            // The Relay error response to the batch request is the response to
+            // each of the coalesced requests.
+            ClientError::BatchFailed(err) => match &*err {
+                ClientError::Rpc {
+                    code,
+                    message,
+                    data,
+                } => {
+                    let err = rpc::ErrorData {
+                        code: *code,
+                        message: message.clone(),
+                        data: data.clone(),
+                    };
 
-                    Err(_) => Error::Client(ClientError::InvalidErrorResponse),
+                    Error::Response(rpc::Error::from_error_data(err))
                 }
-            }
+
+                _ => Error::Client(ClientError::BatchFailed(err)),
+            },
 
             _ => Error::Client(err),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{http::HttpClientError, websocket::WebsocketClientError},
+        http::StatusCode,
+        relay_rpc::rpc::{ErrorData, InternalError, PublishError},
+    };
+
+    fn rpc_error(err: rpc::Error<PublishError>) -> ClientError {
+        ErrorData::from(err).into()
+    }
+
+    #[test]
+    fn classification() {
+        let err = rpc_error(rpc::Error::Internal(InternalError::StorageError));
+        assert!(err.is_retryable());
+        assert_eq!(err.kind(), ErrorKind::Server);
+
+        let err = Error::<PublishError>::from(err);
+        assert!(matches!(err, Error::Response(_)));
+        assert!(err.is_retryable());
+        assert!(err.kind().is_server_fault());
+
+        let err = Error::<PublishError>::from(rpc_error(rpc::Error::Internal(
+            InternalError::Serialization,
+        )));
+        assert!(!err.is_retryable());
+
+        let err = Error::<PublishError>::from(rpc_error(rpc::Error::TooManyRequests));
+        assert!(err.is_retryable());
+        assert_eq!(err.kind(), ErrorKind::RateLimited);
+
+        let err =
+            Error::<PublishError>::from(rpc_error(rpc::Error::Handler(PublishError::TtlTooShort)));
+        assert!(!err.is_retryable());
+        assert!(err.kind().is_client_fault());
+
+        let err = rpc_error(rpc::Error::Auth(rpc::AuthError::InvalidJwt));
+        assert!(err.is_auth_failure());
+        assert!(!err.is_retryable());
+
+        let err = rpc_error(rpc::Error::Payload(rpc::PayloadError::InvalidTopic));
+        assert!(err.is_payload_invalid());
+
+        let err = ClientError::from(WebsocketClientError::NotConnected);
+        assert!(err.is_transient_transport());
+        assert!(err.is_retryable());
+
+        let http_error =
+            |status| ClientError::from(HttpClientError::InvalidHttpCode(status, Ok(String::new())));
+
+        assert!(http_error(StatusCode::BAD_GATEWAY).is_retryable());
+        assert!(http_error(StatusCode::BAD_GATEWAY).kind().is_server_fault());
+        assert!(http_error(StatusCode::UNAUTHORIZED).is_auth_failure());
+        assert!(http_error(StatusCode::BAD_REQUEST).is_payload_invalid());
+        assert!(!http_error(StatusCode::BAD_REQUEST).is_retryable());
+
+        // Unrecognized errors keep the raw error data.
+        let data = ErrorData {
+            code: rpc::CODE_HANDLER,
+            message: "Topic limit exceeded".to_owned(),
+            data: Some("TopicLimitExceeded".to_owned()),
+        };
+        let err = Error::<PublishError>::from(ClientError::from(data.clone()));
+        assert!(matches!(
+            &err,
+            Error::Response(rpc::Error::Unknown { code, data: Some(tag), .. })
+                if *code == rpc::CODE_HANDLER && tag == "TopicLimitExceeded"
+        ));
+        assert_eq!(err.kind(), ErrorKind::Handler);
+
+        let Error::Response(err) = err else {
+            unreachable!()
+        };
+        assert_eq!(ErrorData::from(err), data);
+
+        assert!(!ClientError::ChannelClosed.is_retryable());
+        assert_eq!(ClientError::ChannelClosed.kind(), ErrorKind::Client);
+
+        // Shared batch errors are classified by the underlying error.
+        let err = ClientError::BatchFailed(Arc::new(WebsocketClientError::NotConnected.into()));
+        assert!(err.is_transient_transport());
+        assert!(err.is_retryable());
+
+        let err = ClientError::BatchFailed(Arc::new(rpc_error(rpc::Error::TooManyRequests)));
+        let err = Error::<PublishError>::from(err);
+        assert!(matches!(err, Error::Response(rpc::Error::TooManyRequests)));
+    }
+}
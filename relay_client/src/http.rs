@@ -1,10 +1,15 @@
 use {
     crate::{
-        error::{BoxError, ClientError, Error},
+        batch::{self, DEFAULT_BATCH_CONCURRENCY},
+        error::{impl_kind_predicates, BoxError, ClientError, Error, ErrorKind, RequestBuildError},
+        metrics,
+        rate_limit::{MethodClass, RateLimiter},
+        retry::RetryPolicy,
+        trace::{Outcome, RequestSpan},
         ConnectionOptions,
         MessageIdGenerator,
     },
-    http::{HeaderMap, StatusCode},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
     relay_rpc::{
         auth::ed25519_dalek::SigningKey,
         domain::{DecodedClientId, SubscriptionId, Topic},
@@ -12,6 +17,7 @@ use {
         rpc::{self, Receipt, ServiceRequest},
     },
     std::{sync::Arc, time::Duration},
+    tokio::time::Instant,
     url::Url,
 };
 
@@ -43,6 +49,45 @@ pub enum HttpClientError {
     Jwt(#[from] JwtError),
 }
 
+impl HttpClientError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Transport(err) if err.is_timeout() => ErrorKind::Timeout,
+            Self::Transport(_) => ErrorKind::Transport,
+            Self::InvalidRequest(_) => ErrorKind::Client,
+            Self::InvalidResponse => ErrorKind::Server,
+            Self::Jwt(_) => ErrorKind::Auth,
+
+            Self::InvalidHttpCode(status, _) => match *status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ErrorKind::Auth,
+                StatusCode::TOO_MANY_REQUESTS => ErrorKind::RateLimited,
+                StatusCode::REQUEST_TIMEOUT => ErrorKind::Timeout,
+                status if status.is_client_error() => ErrorKind::InvalidPayload,
+                _ => ErrorKind::Server,
+            },
+        }
+    }
+
+    /// Returns `true` if the request is likely to succeed if repeated later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Transport(_) => true,
+
+            Self::InvalidHttpCode(status, _) => {
+                status.is_server_error()
+                    || matches!(
+                        *status,
+                        StatusCode::TOO_MANY_REQUESTS | StatusCode::REQUEST_TIMEOUT
+                    )
+            }
+
+            _ => false,
+        }
+    }
+}
+
+impl_kind_predicates!(HttpClientError);
+
 #[derive(Debug, Clone)]
 pub struct WatchRegisterRequest {
     /// Service URL.
@@ -76,6 +121,10 @@ pub struct Client {
     url: Url,
     origin: String,
     id_generator: MessageIdGenerator,
+    auth_provider: Option<Arc<ConnectionOptions>>,
+    batch_concurrency: usize,
+    rate_limiter: Option<RateLimiter>,
+    retry_policy: Option<RetryPolicy>,
 }
 
 impl Client {
@@ -83,23 +132,58 @@ impl Client {
         let mut headers = HeaderMap::new();
         opts.update_request_headers(&mut headers)?;
 
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()
-            .map_err(HttpClientError::Transport)?;
+        let mut builder = reqwest::Client::builder().default_headers(headers);
+
+        if let Some(proxy) = &opts.proxy {
+            let proxy = proxy.as_reqwest_proxy().map_err(RequestBuildError::Url)?;
+            builder = builder.proxy(proxy);
+        }
+
+        let client = builder.build().map_err(HttpClientError::Transport)?;
 
         let url = opts.as_url()?;
         let origin = url.origin().unicode_serialization();
         let id_generator = MessageIdGenerator::new();
 
+        // The auth token has to be refreshed for each request if the provider is
+        // set, so keep the options around.
+        let auth_provider = opts.auth_provider.is_some().then(|| Arc::new(opts.clone()));
+
         Ok(Self {
             client,
             url,
             origin,
             id_generator,
+            auth_provider,
+            batch_concurrency: DEFAULT_BATCH_CONCURRENCY,
+            rate_limiter: None,
+            retry_policy: None,
         })
     }
 
+    /// Sets the number of chunks of an oversized batch request that are sent
+    /// concurrently. See [`Client::batch_subscribe()`]. Defaults to
+    /// [`DEFAULT_BATCH_CONCURRENCY`].
+    ///
+    /// [`DEFAULT_BATCH_CONCURRENCY`]: crate::websocket::DEFAULT_BATCH_CONCURRENCY
+    pub fn with_batch_concurrency(mut self, concurrency: usize) -> Self {
+        self.batch_concurrency = concurrency;
+        self
+    }
+
+    /// Sets the client-side rate limiter gating the requests. The limiter can
+    /// be shared with other clients.
+    pub fn with_rate_limiter(mut self, limiter: impl Into<Option<RateLimiter>>) -> Self {
+        self.rate_limiter = limiter.into();
+        self
+    }
+
+    /// Sets the retry policy of the idempotent requests. See [`RetryPolicy`].
+    pub fn with_retry_policy(mut self, policy: impl Into<Option<RetryPolicy>>) -> Self {
+        self.retry_policy = policy.into();
+        self
+    }
+
     pub async fn create_topic(&self, topic: Topic) -> Response<rpc::CreateTopic> {
         self.request(rpc::CreateTopic { topic }).await
     }
@@ -278,14 +362,33 @@ impl Client {
 
     /// Subscribes on multiple topics to receive messages. The request is
     /// resolved optimistically as soon as the relay receives it.
+    ///
+    /// Batches exceeding [`MAX_SUBSCRIPTION_BATCH_SIZE`] are split into chunks
+    /// sent with bounded concurrency (see
+    /// [`Client::with_batch_concurrency()`]). The subscription IDs are
+    /// returned in the order of the topics. This also applies to the other
+    /// batch methods.
+    ///
+    /// Fails with [`ClientError::InvalidBatchResponse`] if the response to a
+    /// chunk doesn't have one ID per topic.
+    ///
+    /// [`MAX_SUBSCRIPTION_BATCH_SIZE`]: rpc::MAX_SUBSCRIPTION_BATCH_SIZE
     pub async fn batch_subscribe(
         &self,
         topics: impl Into<Vec<Topic>>,
     ) -> Response<rpc::BatchSubscribe> {
-        self.request(rpc::BatchSubscribe {
-            topics: topics.into(),
-        })
-        .await
+        let topics = topics.into();
+        let len = topics.len();
+        let requests = batch::chunks(topics, rpc::MAX_SUBSCRIPTION_BATCH_SIZE)
+            .map(|topics| self.request(rpc::BatchSubscribe { topics }));
+
+        let ids = batch::send_chunks(requests, self.batch_concurrency).await?;
+
+        Ok(batch::join_chunks(
+            ids,
+            len,
+            rpc::MAX_SUBSCRIPTION_BATCH_SIZE,
+        )?)
     }
 
     /// Subscribes on multiple topics to receive messages. The request is
@@ -299,14 +402,19 @@ impl Client {
         Vec<Result<SubscriptionId, Error<rpc::SubscriptionError>>>,
         Error<rpc::SubscriptionError>,
     > {
-        Ok(self
-            .request(rpc::BatchSubscribeBlocking {
-                topics: topics.into(),
-            })
-            .await?
-            .into_iter()
-            .map(crate::convert_subscription_result)
-            .collect())
+        let topics = topics.into();
+        let len = topics.len();
+        let requests = batch::chunks(topics, rpc::MAX_SUBSCRIPTION_BATCH_SIZE)
+            .map(|topics| self.request(rpc::BatchSubscribeBlocking { topics }));
+
+        let results = batch::send_chunks(requests, self.batch_concurrency).await?;
+
+        Ok(
+            batch::join_chunks(results, len, rpc::MAX_SUBSCRIPTION_BATCH_SIZE)?
+                .into_iter()
+                .map(crate::convert_subscription_result)
+                .collect(),
+        )
     }
 
     /// Unsubscribes from multiple topics.
@@ -314,10 +422,12 @@ impl Client {
         &self,
         subscriptions: impl Into<Vec<rpc::Unsubscribe>>,
     ) -> Response<rpc::BatchUnsubscribe> {
-        self.request(rpc::BatchUnsubscribe {
-            subscriptions: subscriptions.into(),
-        })
-        .await
+        let requests = batch::chunks(subscriptions.into(), rpc::MAX_SUBSCRIPTION_BATCH_SIZE)
+            .map(|subscriptions| self.request(rpc::BatchUnsubscribe { subscriptions }));
+
+        let results = batch::send_chunks(requests, self.batch_concurrency).await?;
+
+        Ok(results.into_iter().all(|result| result))
     }
 
     /// Fetch mailbox messages for multiple topics.
@@ -325,10 +435,12 @@ impl Client {
         &self,
         topics: impl Into<Vec<Topic>>,
     ) -> Response<rpc::BatchFetchMessages> {
-        self.request(rpc::BatchFetchMessages {
-            topics: topics.into(),
-        })
-        .await
+        let requests = batch::chunks(topics.into(), rpc::MAX_FETCH_BATCH_SIZE)
+            .map(|topics| self.request(rpc::BatchFetchMessages { topics }));
+
+        let responses = batch::send_chunks(requests, self.batch_concurrency).await?;
+
+        Ok(batch::merge_fetch_responses(responses))
     }
 
     /// Acknowledge receipt of messages from a subscribed client.
@@ -336,58 +448,131 @@ impl Client {
         &self,
         receipts: impl Into<Vec<Receipt>>,
     ) -> Response<rpc::BatchReceiveMessages> {
-        self.request(rpc::BatchReceiveMessages {
-            receipts: receipts.into(),
-        })
-        .await
+        let requests = batch::chunks(receipts.into(), rpc::MAX_RECEIVE_BATCH_SIZE)
+            .map(|receipts| self.request(rpc::BatchReceiveMessages { receipts }));
+
+        let results = batch::send_chunks(requests, self.batch_concurrency).await?;
+
+        Ok(results.into_iter().all(|result| result))
+    }
+
+    /// Creates the RPC request builder, refreshing the auth token if the auth
+    /// provider is set.
+    fn authorized_request(&self) -> Result<reqwest::RequestBuilder, ClientError> {
+        let Some(opts) = &self.auth_provider else {
+            return Ok(self.client.post(self.url.clone()));
+        };
+
+        let auth = opts.authorization()?;
+        let url = opts.as_url_with_auth(&auth)?;
+        let mut headers = HeaderMap::new();
+        opts.update_request_headers_with_auth(&mut headers, &auth)?;
+
+        Ok(self.client.post(url).headers(headers))
     }
 
     pub(crate) async fn request<T>(&self, payload: T) -> Response<T>
     where
         T: ServiceRequest,
     {
+        let params = payload.into_params();
+        let retry = self
+            .retry_policy
+            .as_ref()
+            .filter(|policy| policy.applies_to(&params));
+        let mut attempt = 1;
+
+        let response = loop {
+            let result = self.send_request(params.clone()).await;
+
+            match (&result, retry) {
+                (Err(err), Some(policy)) => match policy.retry_delay(attempt, err) {
+                    Some(delay) => {
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+
+                    None => break result,
+                },
+
+                _ => break result,
+            }
+        }?;
+
+        serde_json::from_value(response)
+            .map_err(|_| Error::Client(HttpClientError::InvalidResponse.into()))
+    }
+
+    /// Makes a single attempt of the request, returning the raw result.
+    async fn send_request(&self, params: rpc::Params) -> Result<serde_json::Value, ClientError> {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire(MethodClass::of(&params)).await?;
+        }
+
+        let id = self.id_generator.next();
+        let method = params.method();
+        let span = RequestSpan::new(id, &params);
         let payload = rpc::Payload::Request(rpc::Request {
-            id: self.id_generator.next(),
+            id,
             jsonrpc: rpc::JSON_RPC_VERSION.clone(),
-            params: payload.into_params(),
+            params,
         });
+        let body = serde_json::to_vec(&payload).map_err(ClientError::Serialization)?;
 
-        let response = async {
-            let result = self
-                .client
-                .post(self.url.clone())
-                .json(&payload)
-                .send()
-                .await
-                .map_err(HttpClientError::Transport)?;
+        metrics::request_sent(method);
+        metrics::bytes_sent(body.len());
 
-            let status = result.status();
+        let sent_at = Instant::now();
+        let result = self.post(body).await;
+        let elapsed = sent_at.elapsed();
 
-            if !status.is_success() {
-                let body = result.text().await;
-                return Err(HttpClientError::InvalidHttpCode(status, body));
+        match result {
+            Ok(rpc::Response::Success(response)) => {
+                metrics::response_received(method, elapsed, None);
+                span.finish(Outcome::Success, elapsed);
+                Ok(response.result)
             }
 
-            result
-                .json::<rpc::Payload>()
-                .await
-                .map_err(|_| HttpClientError::InvalidResponse)
-        }
-        .await
-        .map_err(ClientError::from)
-        .map_err(Error::Client)?;
-
-        match response {
-            rpc::Payload::Response(rpc::Response::Success(response)) => {
-                serde_json::from_value(response.result)
-                    .map_err(|_| Error::Client(HttpClientError::InvalidResponse.into()))
+            Ok(rpc::Response::Error(response)) => {
+                metrics::response_received(method, elapsed, Some(&response.error));
+                span.finish(Outcome::Error(&response.error), elapsed);
+                Err(response.error.into())
             }
 
-            rpc::Payload::Response(rpc::Response::Error(response)) => {
-                Err(ClientError::from(response.error).into())
+            Err(err) => {
+                span.finish(Outcome::Failed(&err), elapsed);
+                Err(err)
             }
+        }
+    }
+
+    /// Posts the serialized request and parses the RPC response.
+    async fn post(&self, body: Vec<u8>) -> Result<rpc::Response, ClientError> {
+        let result = self
+            .authorized_request()?
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(HttpClientError::Transport)?;
+
+        let status = result.status();
+
+        if !status.is_success() {
+            let body = result.text().await;
+            return Err(HttpClientError::InvalidHttpCode(status, body).into());
+        }
+
+        let body = result
+            .bytes()
+            .await
+            .map_err(|_| HttpClientError::InvalidResponse)?;
+
+        metrics::bytes_received(body.len());
 
-            _ => Err(Error::Client(HttpClientError::InvalidResponse.into())),
+        match serde_json::from_slice::<rpc::Payload>(&body) {
+            Ok(rpc::Payload::Response(response)) => Ok(response),
+            _ => Err(HttpClientError::InvalidResponse.into()),
         }
     }
 }
@@ -1,5 +1,9 @@
 use {
-    crate::error::{ClientError, RequestBuildError},
+    crate::{
+        auth::{AuthProvider, AuthTokenProvider},
+        error::{ClientError, RequestBuildError},
+        proxy::Proxy,
+    },
     ::http::HeaderMap,
     relay_rpc::{
         auth::{SerializedAuthToken, RELAY_WEBSOCKET_ADDRESS},
@@ -8,15 +12,33 @@ use {
         user_agent::UserAgent,
     },
     serde::Serialize,
-    std::sync::{
-        atomic::{AtomicU8, Ordering},
-        Arc,
+    std::{
+        borrow::Cow,
+        sync::{
+            atomic::{AtomicU8, Ordering},
+            Arc,
+            Mutex,
+            MutexGuard,
+            PoisonError,
+        },
     },
     url::Url,
 };
 
+pub mod auth;
+pub mod backoff;
+mod batch;
 pub mod error;
 pub mod http;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(not(feature = "metrics"))]
+mod metrics;
+pub mod proxy;
+pub mod rate_limit;
+pub mod retry;
+pub mod store;
+mod trace;
 pub mod websocket;
 
 pub type HttpRequest<T> = ::http::Request<T>;
@@ -45,6 +67,12 @@ pub struct ConnectionOptions {
     /// The authorization method and auth token to use.
     pub auth: Authorization,
 
+    /// Optional provider of the fresh auth tokens. If set, a token is obtained
+    /// from the provider before each connection and request, replacing the
+    /// token in [`ConnectionOptions::auth`] while keeping the authorization
+    /// method.
+    pub auth_provider: Option<AuthProvider>,
+
     /// Optional origin of the request. Subject to allow-list validation.
     pub origin: Option<String>,
 
@@ -57,6 +85,9 @@ pub struct ConnectionOptions {
 
     /// Optional user agent parameters.
     pub user_agent: Option<UserAgent>,
+
+    /// Optional proxy used by both the websocket and HTTP clients.
+    pub proxy: Option<Proxy>,
 }
 
 impl ConnectionOptions {
@@ -65,13 +96,32 @@ impl ConnectionOptions {
             address: RELAY_WEBSOCKET_ADDRESS.into(),
             project_id: project_id.into(),
             auth: Authorization::Query(auth),
+            auth_provider: None,
             origin: None,
             user_agent: None,
             package_name: None,
             bundle_id: None,
+            proxy: None,
         }
     }
 
+    /// Creates the options with the auth tokens obtained from the provider
+    /// (see [`ConnectionOptions::with_auth_provider()`]).
+    pub fn from_auth_provider(
+        project_id: impl Into<ProjectId>,
+        provider: impl AuthTokenProvider,
+    ) -> Result<Self, RequestBuildError> {
+        let provider = AuthProvider::new(provider);
+        let auth = provider.token().map_err(RequestBuildError::AuthToken)?;
+
+        Ok(Self::new(project_id, auth).with_auth_provider(provider))
+    }
+
+    pub fn with_auth_provider(mut self, provider: impl Into<Option<AuthProvider>>) -> Self {
+        self.auth_provider = provider.into();
+        self
+    }
+
     pub fn with_address(mut self, address: impl Into<String>) -> Self {
         self.address = address.into();
         self
@@ -97,7 +147,31 @@ impl ConnectionOptions {
         self
     }
 
+    pub fn with_proxy(mut self, proxy: impl Into<Option<Proxy>>) -> Self {
+        self.proxy = proxy.into();
+        self
+    }
+
+    /// Returns the authorization to use for the next connection or request,
+    /// obtaining a fresh token from the provider if it's set.
+    fn authorization(&self) -> Result<Cow<'_, Authorization>, RequestBuildError> {
+        let Some(provider) = &self.auth_provider else {
+            return Ok(Cow::Borrowed(&self.auth));
+        };
+
+        let token = provider.token().map_err(RequestBuildError::AuthToken)?;
+
+        Ok(Cow::Owned(match &self.auth {
+            Authorization::Query(_) => Authorization::Query(token),
+            Authorization::Header(_) => Authorization::Header(token),
+        }))
+    }
+
     pub fn as_url(&self) -> Result<Url, RequestBuildError> {
+        self.as_url_with_auth(&*self.authorization()?)
+    }
+
+    fn as_url_with_auth(&self, auth: &Authorization) -> Result<Url, RequestBuildError> {
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
         struct QueryParams<'a> {
@@ -110,7 +184,7 @@ impl ConnectionOptions {
 
         let query = serde_qs::to_string(&QueryParams {
             project_id: &self.project_id,
-            auth: if let Authorization::Query(auth) = &self.auth {
+            auth: if let Authorization::Query(auth) = auth {
                 Some(auth)
             } else {
                 None
@@ -133,20 +207,34 @@ impl ConnectionOptions {
             tokio_tungstenite::tungstenite::client::IntoClientRequest,
         };
 
-        let url = self.as_url()?;
+        let auth = self.authorization()?;
+        let url = self.as_url_with_auth(&auth)?;
 
         let mut request = url
             .into_client_request()
             .map_err(Box::new)
             .map_err(WebsocketClientError::Transport)?;
 
-        self.update_request_headers(request.headers_mut())?;
+        self.update_request_headers_with_auth(request.headers_mut(), &auth)?;
+
+        // Passed to the transport with the request.
+        if let Some(proxy) = &self.proxy {
+            request.extensions_mut().insert(proxy.clone());
+        }
 
         Ok(request)
     }
 
     fn update_request_headers(&self, headers: &mut HeaderMap) -> Result<(), RequestBuildError> {
-        if let Authorization::Header(token) = &self.auth {
+        self.update_request_headers_with_auth(headers, &*self.authorization()?)
+    }
+
+    fn update_request_headers_with_auth(
+        &self,
+        headers: &mut HeaderMap,
+        auth: &Authorization,
+    ) -> Result<(), RequestBuildError> {
+        if let Authorization::Header(token) = auth {
             let value = format!("Bearer {token}")
                 .parse()
                 .map_err(|_| RequestBuildError::Headers)?;
@@ -164,6 +252,12 @@ impl ConnectionOptions {
     }
 }
 
+/// Locks the mutex, ignoring the poisoning. None of the crate's locks are held
+/// across code that can panic, so the guarded data is never left inconsistent.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
 /// Generates unique message IDs for use in RPC requests. Uses 56 bits for the
 /// timestamp with millisecond precision, with the last 8 bits from a monotonic
 /// counter. Capable of producing up to `256000` unique values per second.
@@ -228,4 +322,35 @@ mod tests {
         let values = (0..256).map(move |_| gen.next()).collect::<Vec<_>>();
         assert!(elements_unique(values));
     }
+
+    #[test]
+    fn auth_provider_tokens() {
+        use {
+            relay_rpc::auth::{ed25519_dalek::SigningKey, AuthToken},
+            std::sync::atomic::AtomicUsize,
+        };
+
+        let key = SigningKey::generate(&mut rand::thread_rng());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let opts = ConnectionOptions::from_auth_provider("test_project_id", {
+            let calls = calls.clone();
+
+            move || {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                AuthToken::new(format!("http://example.com/{n}"))
+                    .as_jwt(&key)
+                    .map_err(Into::into)
+            }
+        })
+        .unwrap();
+
+        let first = opts.as_url().unwrap();
+        let second = opts.as_url().unwrap();
+        assert_ne!(first, second);
+
+        let opts = opts.with_auth_provider(None);
+        assert_eq!(opts.as_url().unwrap(), opts.as_url().unwrap());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
 }
@@ -0,0 +1,171 @@
+//! Client metrics.
+//!
+//! With the `metrics` feature enabled, the HTTP and websocket clients record
+//! the following metrics through the [`metrics`](https://docs.rs/metrics)
+//! facade. Any exporter can collect them by installing a global recorder.
+//! Without the feature, the module is private and nothing is recorded.
+//!
+//! | Name                  | Type      | Labels           |
+//! |-----------------------|-----------|------------------|
+//! | [`REQUESTS`]          | counter   | `method`         |
+//! | [`REQUEST_DURATION`]  | histogram | `method`         |
+//! | [`ERRORS`]            | counter   | `method`, `tag`  |
+//! | [`INBOUND_MESSAGES`]  | counter   | `tag`            |
+//! | [`OUTBOUND_QUEUE`]    | gauge     |                  |
+//! | [`PENDING_REQUESTS`]  | gauge     |                  |
+//! | [`RECONNECTS`]        | counter   |                  |
+//! | [`BYTES_SENT`]        | counter   |                  |
+//! | [`BYTES_RECEIVED`]    | counter   |                  |
+//!
+//! The gauges are shared by all of the connections, each of them adjusting the
+//! value by its own changes.
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables, dead_code))]
+
+use {relay_rpc::rpc::ErrorData, std::time::Duration};
+
+/// RPC requests sent, by the JSON-RPC method.
+pub const REQUESTS: &str = "relay_client_requests_total";
+
+/// Time between sending the request and receiving the response, in seconds.
+pub const REQUEST_DURATION: &str = "relay_client_request_duration_seconds";
+
+/// Error responses received, by the JSON-RPC method and the error tag sent by
+/// the Relay, or the error code if the response has no tag.
+pub const ERRORS: &str = "relay_client_errors_total";
+
+/// Subscription messages received, by the message tag.
+pub const INBOUND_MESSAGES: &str = "relay_client_inbound_messages_total";
+
+/// Requests waiting to be written to the websocket.
+pub const OUTBOUND_QUEUE: &str = "relay_client_outbound_queue_depth";
+
+/// Websocket requests awaiting the response.
+pub const PENDING_REQUESTS: &str = "relay_client_pending_requests";
+
+/// Reconnection attempts of the websocket client.
+pub const RECONNECTS: &str = "relay_client_reconnects_total";
+
+/// Bytes of the messages sent to the Relay.
+pub const BYTES_SENT: &str = "relay_client_bytes_sent_total";
+
+/// Bytes of the messages received from the Relay.
+pub const BYTES_RECEIVED: &str = "relay_client_bytes_received_total";
+
+pub(crate) fn request_sent(method: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(REQUESTS, "method" => method).increment(1);
+}
+
+pub(crate) fn response_received(
+    method: &'static str,
+    elapsed: Duration,
+    error: Option<&ErrorData>,
+) {
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::histogram!(REQUEST_DURATION, "method" => method).record(elapsed);
+
+        if let Some(error) = error {
+            let tag = error.data.clone().unwrap_or_else(|| error.code.to_string());
+            ::metrics::counter!(ERRORS, "method" => method, "tag" => tag).increment(1);
+        }
+    }
+}
+
+pub(crate) fn inbound_message(tag: u32) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(INBOUND_MESSAGES, "tag" => tag.to_string()).increment(1);
+}
+
+pub(crate) fn outbound_queue_changed(delta: f64) {
+    #[cfg(feature = "metrics")]
+    ::metrics::gauge!(OUTBOUND_QUEUE).increment(delta);
+}
+
+pub(crate) fn pending_requests_changed(old: usize, new: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::gauge!(PENDING_REQUESTS).increment(new as f64 - old as f64);
+}
+
+pub(crate) fn reconnect() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(RECONNECTS).increment(1);
+}
+
+pub(crate) fn bytes_sent(bytes: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(BYTES_SENT).increment(bytes as u64);
+}
+
+pub(crate) fn bytes_received(bytes: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(BYTES_RECEIVED).increment(bytes as u64);
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use {
+        super::*,
+        metrics_util::{
+            debugging::{DebugValue, DebuggingRecorder},
+            CompositeKey,
+            MetricKind,
+        },
+        relay_rpc::rpc::CODE_HANDLER,
+    };
+
+    fn labels(key: &CompositeKey) -> Vec<(String, String)> {
+        key.key()
+            .labels()
+            .map(|label| (label.key().to_owned(), label.value().to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn record_request() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        ::metrics::with_local_recorder(&recorder, || {
+            let error = ErrorData {
+                code: CODE_HANDLER,
+                message: "New handler error".into(),
+                data: Some("NewHandlerError".into()),
+            };
+
+            request_sent("irn_publish");
+            response_received("irn_publish", Duration::from_millis(250), Some(&error));
+        });
+
+        let method = ("method".to_owned(), "irn_publish".to_owned());
+        let mut recorded = 0;
+
+        for (key, _, _, value) in snapshotter.snapshot().into_vec() {
+            match (key.kind(), key.key().name(), value) {
+                (MetricKind::Counter, REQUESTS, DebugValue::Counter(count)) => {
+                    assert_eq!(labels(&key), vec![method.clone()]);
+                    assert_eq!(count, 1);
+                }
+
+                (MetricKind::Counter, ERRORS, DebugValue::Counter(count)) => {
+                    // The unrecognized tags are preserved.
+                    let tag = ("tag".to_owned(), "NewHandlerError".to_owned());
+                    assert_eq!(labels(&key), vec![method.clone(), tag]);
+                    assert_eq!(count, 1);
+                }
+
+                (MetricKind::Histogram, REQUEST_DURATION, DebugValue::Histogram(values)) => {
+                    assert_eq!(labels(&key), vec![method.clone()]);
+                    assert_eq!(values.len(), 1);
+                    assert_eq!(values.first().map(|value| value.into_inner()), Some(0.25));
+                }
+
+                (_, name, _) => panic!("unexpected metric: {name}"),
+            }
+
+            recorded += 1;
+        }
+
+        assert_eq!(recorded, 3);
+    }
+}
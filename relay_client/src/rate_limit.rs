@@ -0,0 +1,304 @@
+use {
+    crate::ClientError,
+    relay_rpc::rpc::Params,
+    std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    tokio::time::{sleep_until, Instant},
+};
+
+/// Class of the RPC methods sharing the same rate limit budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MethodClass {
+    /// `irn_publish`, `wc_proposeSession` and `wc_approveSession`.
+    Publish,
+
+    /// `irn_subscribe`, `irn_unsubscribe` and their blocking and batch
+    /// variants.
+    Subscribe,
+
+    /// `irn_fetchMessages` and `irn_batchFetchMessages`.
+    Fetch,
+
+    /// All of the other methods.
+    Other,
+}
+
+impl MethodClass {
+    pub fn of(params: &Params) -> Self {
+        match params {
+            Params::Publish(_) | Params::ProposeSession(_) | Params::ApproveSession(_) => {
+                Self::Publish
+            }
+
+            Params::Subscribe(_)
+            | Params::SubscribeBlocking(_)
+            | Params::Unsubscribe(_)
+            | Params::BatchSubscribe(_)
+            | Params::BatchSubscribeBlocking(_)
+            | Params::BatchUnsubscribe(_) => Self::Subscribe,
+
+            Params::FetchMessages(_) | Params::BatchFetchMessages(_) => Self::Fetch,
+
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Token bucket budget. The bucket holds up to `capacity` tokens, allowing
+/// bursts of that many requests, and is refilled with one token per
+/// `refill_interval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub refill_interval: Duration,
+}
+
+impl RateLimit {
+    pub fn new(capacity: u32, refill_interval: Duration) -> Self {
+        Self {
+            capacity,
+            refill_interval,
+        }
+    }
+
+    /// Allows the specified number of requests per second, with the bursts of
+    /// the same size.
+    ///
+    /// A budget of zero requests denies all of the requests of the class,
+    /// which fail with [`ClientError::RateLimited`] regardless of the
+    /// [`RateLimitMode`].
+    pub fn per_second(requests: u32) -> Self {
+        let refill_interval = Duration::from_secs(1)
+            .checked_div(requests)
+            .unwrap_or(Duration::MAX);
+
+        Self::new(requests, refill_interval)
+    }
+}
+
+/// Behavior when the rate limit budget is exhausted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RateLimitMode {
+    /// Wait for the budget to be refilled before sending the request.
+    #[default]
+    Wait,
+
+    /// Fail the request with [`ClientError::RateLimited`].
+    FailFast,
+}
+
+/// Client-side rate limiter configuration.
+///
+/// Keeps the outbound requests within the Relay quotas, instead of having them
+/// rejected with the `TooManyRequests` error. Each [`MethodClass`] has its own
+/// budget, falling back to the default one. The requests of the classes
+/// without a budget are not limited.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiterConfig {
+    /// Budget of the method classes without their own budget.
+    pub default: Option<RateLimit>,
+
+    /// Budgets of the individual method classes.
+    pub classes: HashMap<MethodClass, RateLimit>,
+
+    /// Behavior when the budget is exhausted.
+    pub mode: RateLimitMode,
+}
+
+impl RateLimiterConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_default_limit(mut self, limit: impl Into<Option<RateLimit>>) -> Self {
+        self.default = limit.into();
+        self
+    }
+
+    pub fn with_limit(mut self, class: MethodClass, limit: RateLimit) -> Self {
+        self.classes.insert(class, limit);
+        self
+    }
+
+    pub fn with_mode(mut self, mode: RateLimitMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: u32,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.capacity,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token if available. Otherwise returns the time the next token
+    /// becomes available, or `None` if the bucket is never refilled.
+    fn try_take(&mut self) -> Result<(), Option<Instant>> {
+        let now = Instant::now();
+
+        if self.tokens < self.limit.capacity {
+            let elapsed = now.duration_since(self.refilled_at);
+            let refill = elapsed.as_nanos() / self.limit.refill_interval.as_nanos().max(1);
+            let refill = u32::try_from(refill).unwrap_or(u32::MAX);
+
+            if refill > 0 {
+                self.tokens = self.tokens.saturating_add(refill).min(self.limit.capacity);
+                self.refilled_at = if self.tokens == self.limit.capacity {
+                    now
+                } else {
+                    self.refilled_at + self.limit.refill_interval * refill
+                };
+            }
+        } else {
+            self.refilled_at = now;
+        }
+
+        if self.tokens > 0 {
+            self.tokens -= 1;
+            return Ok(());
+        }
+
+        if self.limit.capacity == 0 {
+            return Err(None);
+        }
+
+        Err(self.refilled_at.checked_add(self.limit.refill_interval))
+    }
+}
+
+/// Token bucket rate limiter shared by the clones of a client. See
+/// [`RateLimiterConfig`].
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    buckets: Arc<HashMap<MethodClass, Mutex<Bucket>>>,
+    mode: RateLimitMode,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimiterConfig) -> Self {
+        let classes = [
+            MethodClass::Publish,
+            MethodClass::Subscribe,
+            MethodClass::Fetch,
+            MethodClass::Other,
+        ];
+
+        let buckets = classes
+            .into_iter()
+            .filter_map(|class| {
+                let limit = config.classes.get(&class).copied().or(config.default)?;
+                Some((class, Mutex::new(Bucket::new(limit))))
+            })
+            .collect();
+
+        Self {
+            buckets: Arc::new(buckets),
+            mode: config.mode,
+        }
+    }
+
+    pub fn mode(&self) -> RateLimitMode {
+        self.mode
+    }
+
+    /// Takes a permit for the request without waiting. Fails with
+    /// [`ClientError::RateLimited`] if the budget is exhausted.
+    pub fn try_acquire(&self, class: MethodClass) -> Result<(), ClientError> {
+        self.try_take(class).map_err(|_| ClientError::RateLimited)
+    }
+
+    /// Takes a permit for the request, according to the [`RateLimitMode`].
+    /// Fails with [`ClientError::RateLimited`] without waiting if the budget is
+    /// never refilled.
+    pub async fn acquire(&self, class: MethodClass) -> Result<(), ClientError> {
+        loop {
+            match self.try_take(class) {
+                Ok(()) => return Ok(()),
+
+                Err(Some(available_at)) if self.mode == RateLimitMode::Wait => {
+                    sleep_until(available_at).await
+                }
+
+                Err(_) => return Err(ClientError::RateLimited),
+            }
+        }
+    }
+
+    fn try_take(&self, class: MethodClass) -> Result<(), Option<Instant>> {
+        let Some(bucket) = self.buckets.get(&class) else {
+            return Ok(());
+        };
+
+        crate::lock(bucket).try_take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn token_bucket() {
+        let limiter = RateLimiter::new(
+            RateLimiterConfig::new()
+                .with_limit(MethodClass::Publish, RateLimit::per_second(2))
+                .with_mode(RateLimitMode::FailFast),
+        );
+
+        // Burst up to the capacity.
+        assert!(limiter.acquire(MethodClass::Publish).await.is_ok());
+        assert!(limiter.acquire(MethodClass::Publish).await.is_ok());
+        assert!(matches!(
+            limiter.acquire(MethodClass::Publish).await,
+            Err(ClientError::RateLimited)
+        ));
+
+        // Other classes are not limited.
+        for _ in 0..10 {
+            assert!(limiter.try_acquire(MethodClass::Subscribe).is_ok());
+        }
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(limiter.try_acquire(MethodClass::Publish).is_ok());
+        assert!(limiter.try_acquire(MethodClass::Publish).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_permit() {
+        let limiter =
+            RateLimiter::new(RateLimiterConfig::new().with_default_limit(RateLimit::per_second(1)));
+
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire(MethodClass::Fetch).await.unwrap();
+        }
+
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deny_all() {
+        let limiter =
+            RateLimiter::new(RateLimiterConfig::new().with_default_limit(RateLimit::per_second(0)));
+
+        assert!(matches!(
+            limiter.acquire(MethodClass::Publish).await,
+            Err(ClientError::RateLimited)
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+use {
+    crate::{backoff::Backoff, ClientError},
+    relay_rpc::rpc::Params,
+    std::{fmt, sync::Arc, time::Duration},
+};
+
+/// Default maximum number of attempts, including the first one.
+pub const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+
+type Classifier = Arc<dyn Fn(&ClientError) -> bool + Send + Sync>;
+
+/// Retry policy for the idempotent RPC requests.
+///
+/// Requests failing with a retryable error are repeated after a jittered
+/// exponential [`Backoff`] delay, until they succeed, fail with a permanent
+/// error, or run out of attempts. Only the methods that are safe to repeat are
+/// retried (see [`RetryPolicy::applies_to()`]). Note that `irn_publish` is
+/// considered safe, since the Relay de-duplicates the messages by their hash.
+///
+/// By default, the transport failures, HTTP 5xx and 429 statuses, and the
+/// `TooManyRequests` and `StorageError` Relay errors are retried (see
+/// [`ClientError::is_retryable()`]). The classification can be replaced with
+/// [`RetryPolicy::with_classifier()`].
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// Delay before each of the repeated attempts. The first retry is delayed
+    /// by [`Backoff::initial_delay`].
+    pub backoff: Backoff,
+
+    /// The maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+
+    classifier: Classifier,
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Sets the function deciding which errors are retried.
+    pub fn with_classifier(
+        mut self,
+        classifier: impl Fn(&ClientError) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.classifier = Arc::new(classifier);
+        self
+    }
+
+    /// Returns `true` if the request method is safe to retry.
+    pub fn applies_to(&self, params: &Params) -> bool {
+        matches!(
+            params,
+            Params::Publish(_)
+                | Params::Subscribe(_)
+                | Params::SubscribeBlocking(_)
+                | Params::Unsubscribe(_)
+                | Params::FetchMessages(_)
+                | Params::BatchSubscribe(_)
+                | Params::BatchSubscribeBlocking(_)
+                | Params::BatchUnsubscribe(_)
+                | Params::BatchFetchMessages(_)
+                | Params::BatchReceiveMessages(_)
+        )
+    }
+
+    /// Returns the delay before the next attempt if the failed attempt should
+    /// be retried. Attempts are counted starting from `1`.
+    pub fn retry_delay(&self, attempt: u32, err: &ClientError) -> Option<Duration> {
+        (attempt < self.max_attempts && (self.classifier)(err)).then(|| self.backoff.delay(attempt))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::default(),
+            max_attempts: DEFAULT_RETRY_ATTEMPTS,
+            classifier: Arc::new(ClientError::is_retryable),
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("backoff", &self.backoff)
+            .field("max_attempts", &self.max_attempts)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attempts() {
+        let policy = RetryPolicy::new()
+            .with_backoff(
+                Backoff::new(Duration::from_millis(100), Duration::from_secs(1)).with_jitter(0.0),
+            )
+            .with_max_attempts(3);
+        let err = ClientError::RequestTimeout;
+
+        assert_eq!(
+            policy.retry_delay(1, &err),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            policy.retry_delay(2, &err),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(policy.retry_delay(3, &err), None);
+
+        let policy = policy.with_classifier(|_| false);
+        assert_eq!(policy.retry_delay(1, &err), None);
+    }
+}
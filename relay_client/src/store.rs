@@ -0,0 +1,178 @@
+use {
+    crate::error::BoxError,
+    relay_rpc::domain::{SubscriptionId, Topic},
+    std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex, MutexGuard},
+    },
+};
+
+pub type Subscriptions = HashMap<Topic, SubscriptionId>;
+
+/// Persistent record of the topics the client is subscribed to, along with
+/// their subscription IDs.
+///
+/// The websocket client records each successful subscription in the store and
+/// removes the topics it unsubscribes from. When connecting, the stored topics
+/// are re-subscribed with batch subscribe requests, so that the subscriptions
+/// survive process restarts (see [`ClientConfig::subscription_store`]).
+///
+/// The subscriptions are loaded when the client connects for the first time.
+/// The changes are written by a background task on the blocking thread pool, so
+/// the store lags briefly behind the requests. The changes made in quick
+/// succession are coalesced into a single write.
+///
+/// [`ClientConfig::subscription_store`]: crate::websocket::ClientConfig::subscription_store
+pub trait SubscriptionStore: std::fmt::Debug + Send + Sync + 'static {
+    /// Returns all of the stored subscriptions.
+    fn load(&self) -> Result<Subscriptions, BoxError>;
+
+    /// Records the subscriptions, replacing the IDs of the already stored
+    /// topics.
+    fn insert(&self, subscriptions: Vec<(Topic, SubscriptionId)>) -> Result<(), BoxError>;
+
+    /// Removes the subscriptions of the topics.
+    fn remove(&self, topics: &[Topic]) -> Result<(), BoxError>;
+}
+
+/// In-memory [`SubscriptionStore`]. The clones share the same subscriptions.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySubscriptionStore {
+    subscriptions: Arc<Mutex<Subscriptions>>,
+}
+
+impl MemorySubscriptionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Subscriptions> {
+        crate::lock(&self.subscriptions)
+    }
+}
+
+impl SubscriptionStore for MemorySubscriptionStore {
+    fn load(&self) -> Result<Subscriptions, BoxError> {
+        Ok(self.lock().clone())
+    }
+
+    fn insert(&self, subscriptions: Vec<(Topic, SubscriptionId)>) -> Result<(), BoxError> {
+        self.lock().extend(subscriptions);
+        Ok(())
+    }
+
+    fn remove(&self, topics: &[Topic]) -> Result<(), BoxError> {
+        let mut subscriptions = self.lock();
+
+        for topic in topics {
+            subscriptions.remove(topic);
+        }
+
+        Ok(())
+    }
+}
+
+/// [`SubscriptionStore`] persisting the subscriptions to a JSON file.
+///
+/// The subscriptions are kept in memory and the whole file is rewritten on each
+/// write. The file is replaced atomically, by writing to a temporary file next
+/// to it first.
+#[derive(Debug)]
+pub struct JsonFileSubscriptionStore {
+    path: PathBuf,
+    subscriptions: Mutex<Subscriptions>,
+}
+
+impl JsonFileSubscriptionStore {
+    /// Opens the store, loading the subscriptions from the file if it exists.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let path = path.as_ref().to_path_buf();
+
+        let subscriptions = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Subscriptions::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self {
+            path,
+            subscriptions: Mutex::new(subscriptions),
+        })
+    }
+
+    /// Path of the store file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Subscriptions)) -> Result<(), BoxError> {
+        let mut subscriptions = crate::lock(&self.subscriptions);
+
+        update(&mut subscriptions);
+
+        let data = serde_json::to_vec(&*subscriptions)?;
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+impl SubscriptionStore for JsonFileSubscriptionStore {
+    fn load(&self) -> Result<Subscriptions, BoxError> {
+        Ok(crate::lock(&self.subscriptions).clone())
+    }
+
+    fn insert(&self, subscriptions: Vec<(Topic, SubscriptionId)>) -> Result<(), BoxError> {
+        self.update(|stored| stored.extend(subscriptions))
+    }
+
+    fn remove(&self, topics: &[Topic]) -> Result<(), BoxError> {
+        self.update(|stored| {
+            for topic in topics {
+                stored.remove(topic);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_file_store() {
+        let path = std::env::temp_dir().join(format!(
+            "relay_client_subscriptions_{}.json",
+            SubscriptionId::generate()
+        ));
+
+        let first = Topic::generate();
+        let second = Topic::generate();
+
+        let store = JsonFileSubscriptionStore::open(&path).unwrap();
+        assert!(store.load().unwrap().is_empty());
+
+        store
+            .insert(vec![
+                (first.clone(), SubscriptionId::generate()),
+                (second.clone(), SubscriptionId::generate()),
+            ])
+            .unwrap();
+        store.remove(&[first]).unwrap();
+
+        let expected = store.load().unwrap();
+        assert_eq!(expected.len(), 1);
+        assert!(expected.contains_key(&second));
+
+        // Reopening the store restores the subscriptions.
+        let reopened = JsonFileSubscriptionStore::open(&path).unwrap();
+        assert_eq!(reopened.load().unwrap(), expected);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
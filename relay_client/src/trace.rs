@@ -0,0 +1,277 @@
+//! Tracing instrumentation of the Relay RPCs.
+//!
+//! With the `tracing` feature enabled, each outbound request gets a
+//! `relay_request` span recording the request ID, method, topic count, the
+//! first few topics, tag and TTL, and then the outcome and latency once the
+//! request completes. Each inbound subscription message produces an event with
+//! the topic, tag and message hash. The message bodies are never recorded.
+#![cfg_attr(not(feature = "tracing"), allow(unused_variables, dead_code))]
+
+use {
+    crate::ClientError,
+    relay_rpc::{
+        domain::MessageId,
+        rpc::{ErrorData, Params, SubscriptionData},
+    },
+    std::time::Duration,
+};
+
+/// The maximum number of topics recorded in the request span, so that the
+/// large batches don't produce huge spans.
+const MAX_SPAN_TOPICS: usize = 3;
+
+/// Result of a traced request.
+pub(crate) enum Outcome<'a> {
+    /// The Relay responded with a result.
+    Success,
+
+    /// The Relay responded with an error.
+    Error(&'a ErrorData),
+
+    /// The request failed without a response.
+    Failed(&'a ClientError),
+
+    /// The response future was dropped before the response arrived.
+    Cancelled,
+}
+
+/// Span covering an outbound request, from sending it to its completion.
+pub(crate) struct RequestSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl RequestSpan {
+    pub(crate) fn new(id: MessageId, params: &Params) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: request_span(id, params),
+        }
+    }
+
+    /// Records the outcome and the latency of the request.
+    pub(crate) fn finish(&self, outcome: Outcome<'_>, elapsed: Duration) {
+        #[cfg(feature = "tracing")]
+        {
+            let latency_ms = elapsed.as_secs_f64() * 1000.0;
+            let _guard = self.span.enter();
+
+            match outcome {
+                Outcome::Success => {
+                    self.span.record("outcome", "success");
+                    tracing::debug!(latency_ms, "relay request succeeded");
+                }
+
+                Outcome::Error(error) => {
+                    self.span.record("outcome", "error");
+                    tracing::debug!(
+                        latency_ms,
+                        code = error.code,
+                        tag = error.data.as_deref(),
+                        "relay request failed: {}",
+                        error.message
+                    );
+                }
+
+                Outcome::Failed(error) => {
+                    self.span.record("outcome", "failed");
+                    tracing::debug!(latency_ms, "relay request failed: {error}");
+                }
+
+                Outcome::Cancelled => {
+                    self.span.record("outcome", "cancelled");
+                    tracing::debug!(latency_ms, "relay request cancelled");
+                }
+            }
+
+            self.span.record("latency_ms", latency_ms);
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn request_span(id: MessageId, params: &Params) -> tracing::Span {
+    use tracing::field::Empty;
+
+    let span = tracing::debug_span!(
+        "relay_request",
+        id = %id,
+        method = params.method(),
+        topic_count = Empty,
+        topics = Empty,
+        tag = Empty,
+        ttl = Empty,
+        outcome = Empty,
+        latency_ms = Empty,
+    );
+
+    if span.is_disabled() {
+        return span;
+    }
+
+    let topics = match params {
+        Params::CreateTopic(data) => vec![&data.topic],
+        Params::ProposeSession(data) => vec![&data.pairing_topic],
+        Params::ApproveSession(data) => vec![&data.pairing_topic, &data.session_topic],
+        Params::Subscribe(data) => vec![&data.topic],
+        Params::SubscribeBlocking(data) => vec![&data.topic],
+        Params::Unsubscribe(data) => vec![&data.topic],
+        Params::FetchMessages(data) => vec![&data.topic],
+        Params::BatchSubscribe(data) => data.topics.iter().collect(),
+        Params::BatchSubscribeBlocking(data) => data.topics.iter().collect(),
+        Params::BatchUnsubscribe(data) => data.subscriptions.iter().map(|sub| &sub.topic).collect(),
+        Params::BatchFetchMessages(data) => data.topics.iter().collect(),
+        Params::Publish(data) => vec![&data.topic],
+        Params::BatchReceiveMessages(data) => {
+            data.receipts.iter().map(|receipt| &receipt.topic).collect()
+        }
+        Params::Subscription(data) => vec![&data.data.topic],
+        Params::WatchRegister(_) | Params::WatchUnregister(_) => Vec::new(),
+    };
+
+    if !topics.is_empty() {
+        span.record("topic_count", topics.len());
+
+        let topics = topics
+            .iter()
+            .take(MAX_SPAN_TOPICS)
+            .map(|topic| topic.value().as_ref())
+            .collect::<Vec<&str>>();
+
+        span.record("topics", topics.join(","));
+    }
+
+    if let Params::Publish(data) = params {
+        span.record("tag", data.tag);
+        span.record("ttl", data.ttl_secs);
+    }
+
+    span
+}
+
+/// Records the inbound subscription message.
+pub(crate) fn inbound_message(id: MessageId, data: &SubscriptionData) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        id = %id,
+        topic = %data.topic,
+        tag = data.tag,
+        msg_id = relay_rpc::rpc::msg_id::get_message_id(&data.message),
+        "relay message received"
+    );
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use {
+        super::*,
+        relay_rpc::{
+            domain::Topic,
+            rpc::{BatchSubscribe, Publish},
+        },
+        std::{
+            fmt,
+            sync::{Arc, Mutex},
+        },
+        tracing::{
+            field::{Field, Visit},
+            span::{Attributes, Id, Record},
+            Subscriber,
+        },
+        tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer},
+    };
+
+    /// Layer capturing the fields of the `relay_request` spans.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Visit for Capture {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            crate::lock(&self.0).push((field.name().to_owned(), value.to_owned()));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            crate::lock(&self.0).push((field.name().to_owned(), format!("{value:?}")));
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            if attrs.metadata().name() == "relay_request" {
+                attrs.record(&mut self.clone());
+            }
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+            if ctx
+                .span(id)
+                .is_some_and(|span| span.name() == "relay_request")
+            {
+                values.record(&mut self.clone());
+            }
+        }
+    }
+
+    /// Returns the fields of the span of the request completed successfully.
+    fn span_fields(params: &Params) -> Vec<(String, String)> {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = RequestSpan::new(MessageId::new(42), params);
+            span.finish(Outcome::Success, Duration::from_millis(5));
+        });
+
+        let fields = crate::lock(&capture.0).clone();
+        fields
+    }
+
+    fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn request_span() {
+        let topic = Topic::generate();
+        let fields = span_fields(&Params::Publish(Publish {
+            topic: topic.clone(),
+            message: "message".into(),
+            attestation: None,
+            ttl_secs: 300,
+            tag: 1100,
+            prompt: false,
+            analytics: None,
+        }));
+        let field = |name: &str| field(&fields, name);
+
+        assert_eq!(field("id"), Some("42"));
+        assert_eq!(field("method"), Some("irn_publish"));
+        assert_eq!(field("topic_count"), Some("1"));
+        assert_eq!(field("topics"), Some(topic.value().as_ref()));
+        assert_eq!(field("tag"), Some("1100"));
+        assert_eq!(field("ttl"), Some("300"));
+        assert_eq!(field("outcome"), Some("success"));
+        assert_eq!(field("latency_ms"), Some("5.0"));
+    }
+
+    #[test]
+    fn batch_request_span() {
+        let topics: Vec<_> = (0..500).map(|_| Topic::generate()).collect();
+        let fields = span_fields(&Params::BatchSubscribe(BatchSubscribe {
+            topics: topics.clone(),
+        }));
+
+        let recorded = topics
+            .iter()
+            .take(MAX_SPAN_TOPICS)
+            .map(|topic| topic.value().as_ref())
+            .collect::<Vec<&str>>()
+            .join(",");
+
+        assert_eq!(field(&fields, "topic_count"), Some("500"));
+        assert_eq!(field(&fields, "topics"), Some(recorded.as_str()));
+    }
+}
@@ -1,9 +1,17 @@
 use {
-    self::connection::{connection_event_loop, ConnectionControl},
+    self::{
+        connection::{connection_event_loop, ConnectionControl},
+        dedup::DedupFilter,
+        dispatch::Dispatcher,
+        heartbeat::RoundTripTime,
+    },
     crate::{
-        error::{ClientError, Error},
+        error::{impl_kind_predicates, ClientError, Error, ErrorKind},
+        rate_limit::{MethodClass, RateLimitMode, RateLimiter},
+        retry::RetryPolicy,
         ConnectionOptions,
     },
+    http::StatusCode,
     relay_rpc::{
         domain::{MessageId, SubscriptionId, Topic},
         rpc::{
@@ -19,26 +27,41 @@ use {
             ProposeSession,
             Publish,
             Receipt,
+            ServiceRequest,
             SessionProperties,
             Subscribe,
             SubscribeBlocking,
             Subscription,
             SubscriptionError,
             Unsubscribe,
+            MAX_FETCH_BATCH_SIZE,
+            MAX_RECEIVE_BATCH_SIZE,
+            MAX_SUBSCRIPTION_BATCH_SIZE,
         },
     },
     std::{future::Future, sync::Arc, time::Duration},
     tokio::sync::{
-        mpsc::{self, UnboundedSender},
+        mpsc::{self, error::TrySendError, Sender},
         oneshot,
+        watch,
     },
 };
 pub use {
+    ack::*,
+    config::*,
+    dedup::*,
+    events::*,
     fetch::*,
     inbound::*,
+    offline::*,
     outbound::*,
+    receipt::*,
+    shard::*,
+    state::*,
     stream::*,
+    subscription::*,
     tokio_tungstenite::tungstenite::protocol::CloseFrame,
+    transport::*,
 };
 
 pub type RawTransportError = tokio_tungstenite::tungstenite::Error;
@@ -58,10 +81,45 @@ pub enum WebsocketClientError {
     #[error("Websocket transport error: {0}")]
     Transport(TransportError),
 
+    #[error("Proxy error: {0}")]
+    Proxy(#[from] crate::proxy::ProxyError),
+
     #[error("Not connected")]
     NotConnected,
 }
 
+impl WebsocketClientError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::ConnectionFailed(err) => match &**err {
+                RawTransportError::Http(response)
+                    if matches!(
+                        response.status(),
+                        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+                    ) =>
+                {
+                    ErrorKind::Auth
+                }
+
+                _ => ErrorKind::Transport,
+            },
+
+            Self::ConnectionClosed(_)
+            | Self::ClosingFailed(_)
+            | Self::Transport(_)
+            | Self::Proxy(_)
+            | Self::NotConnected => ErrorKind::Transport,
+        }
+    }
+
+    /// Returns `true` if the request is likely to succeed if repeated later.
+    pub fn is_retryable(&self) -> bool {
+        self.is_transient_transport()
+    }
+}
+
+impl_kind_predicates!(WebsocketClientError);
+
 /// Wrapper around the websocket [`CloseFrame`] providing info about the
 /// connection closing reason.
 #[derive(Debug, Clone)]
@@ -77,11 +135,27 @@ impl std::fmt::Display for CloseReason {
     }
 }
 
+mod ack;
+mod coalesce;
+mod config;
 mod connection;
+mod dedup;
+mod dispatch;
+mod events;
 mod fetch;
+mod heartbeat;
 mod inbound;
+mod offline;
 mod outbound;
+mod persist;
+mod receipt;
+mod shard;
+mod state;
 mod stream;
+mod subscription;
+#[cfg(test)]
+mod tests;
+mod transport;
 
 /// The message received from a subscription.
 #[derive(Debug, Clone)]
@@ -111,9 +185,20 @@ impl PublishedMessage {
             received_at: now,
         }
     }
+
+    /// Returns the receipt acknowledging the message with `irn_batchReceive`.
+    pub fn receipt(&self) -> Receipt {
+        Receipt {
+            topic: self.topic.clone(),
+            message_id: self.message_id,
+        }
+    }
 }
 
 /// Handlers for the RPC stream events.
+///
+/// The handler runs in a dedicated task and receives the events through a
+/// bounded queue (see [`ClientConfig::inbound_capacity`]).
 pub trait ConnectionHandler: Send + 'static {
     /// Called when a connection to the Relay is established.
     fn connected(&mut self) {}
@@ -121,9 +206,29 @@ pub trait ConnectionHandler: Send + 'static {
     /// Called when the Relay connection is closed.
     fn disconnected(&mut self, _frame: Option<CloseFrame>) {}
 
+    /// Called when a reconnection attempt is scheduled after the connection
+    /// was lost. Only used if the client has a [`ReconnectPolicy`] configured.
+    fn reconnecting(&mut self, _attempt: u32, _delay: Duration) {}
+
+    /// Called when a reconnection attempt fails. If the [`ReconnectPolicy`]
+    /// allows more attempts, the next one is scheduled afterwards.
+    fn reconnect_failed(&mut self, _attempt: u32, _error: ClientError) {}
+
     /// Called when a message is received from the Relay.
     fn message_received(&mut self, message: PublishedMessage);
 
+    /// Called instead of [`ConnectionHandler::message_received()`] when a
+    /// message is received in the manual acknowledgement mode (see
+    /// [`ClientConfig::manual_ack`]). The message must be acknowledged with
+    /// [`AckHandle::ack()`] or [`AckHandle::nack()`] once processed.
+    ///
+    /// The default implementation acknowledges the message right after passing
+    /// it to [`ConnectionHandler::message_received()`].
+    fn message_received_with_ack(&mut self, message: PublishedMessage, ack: AckHandle) {
+        self.message_received(message);
+        ack.ack().ok();
+    }
+
     /// Called when an inbound error occurs, such as data deserialization
     /// failure, or an unknown response message ID.
     fn inbound_error(&mut self, _error: ClientError) {}
@@ -134,6 +239,7 @@ pub trait ConnectionHandler: Send + 'static {
 }
 
 type SubscriptionResult<T> = Result<T, Error<SubscriptionError>>;
+type BatchResult<T> = Result<<T as ServiceRequest>::Response, Error<<T as ServiceRequest>::Error>>;
 
 /// The Relay WebSocket RPC client.
 ///
@@ -141,7 +247,15 @@ type SubscriptionResult<T> = Result<T, Error<SubscriptionError>>;
 /// a lower-level RPC stream see [`ClientStream`](crate::client::ClientStream).
 #[derive(Debug, Clone)]
 pub struct Client {
-    control_tx: UnboundedSender<ConnectionControl>,
+    control_tx: Sender<ConnectionControl>,
+    rtt: RoundTripTime,
+    request_timeout: Option<Duration>,
+    inbound_capacity: usize,
+    batch_concurrency: usize,
+    dedup: Option<DedupFilter>,
+    rate_limiter: Option<RateLimiter>,
+    retry_policy: Option<RetryPolicy>,
+    state: watch::Receiver<ConnectionState>,
 }
 
 impl Client {
@@ -150,19 +264,87 @@ impl Client {
     where
         T: ConnectionHandler,
     {
-        let (control_tx, control_rx) = mpsc::unbounded_channel();
+        Self::with_config(handler, ClientConfig::default())
+    }
+
+    /// Creates a new [`Client`] with the provided handler and configuration.
+    pub fn with_config<T>(handler: T, config: ClientConfig) -> Self
+    where
+        T: ConnectionHandler,
+    {
+        let dispatcher = Dispatcher::spawn(handler, config.inbound_capacity);
 
-        tokio::spawn(connection_event_loop(control_rx, handler));
+        Self::spawn(dispatcher, config)
+    }
 
-        Self { control_tx }
+    /// Creates a new [`Client`], returning it along with the
+    /// [`ClientEventStream`] of the connection events, as an alternative to
+    /// the [`ConnectionHandler`] callbacks.
+    pub fn new_with_stream() -> (Self, ClientEventStream) {
+        Self::with_config_and_stream(ClientConfig::default())
     }
 
-    pub fn create_topic(&self, topic: Topic) -> ResponseFuture<CreateTopic> {
-        let (request, response) = create_request(CreateTopic { topic });
+    /// Creates a new [`Client`] with the provided configuration, returning it
+    /// along with the [`ClientEventStream`] of the connection events.
+    pub fn with_config_and_stream(config: ClientConfig) -> (Self, ClientEventStream) {
+        let (dispatcher, stream) = Dispatcher::stream(config.inbound_capacity);
+
+        (Self::spawn(dispatcher, config), stream)
+    }
+
+    fn spawn(dispatcher: Dispatcher, config: ClientConfig) -> Self {
+        let (control_tx, control_rx) = mpsc::channel(config.request_capacity.max(1));
+        let rtt = RoundTripTime::default();
+        let request_timeout = config.request_timeout;
+        let inbound_capacity = config.inbound_capacity;
+        let batch_concurrency = config.batch_concurrency;
+        let dedup = config.dedup.clone().map(DedupFilter::new);
+        let rate_limiter = config.rate_limiter.clone();
+        let retry_policy = config.retry.clone();
+        let (state_tx, state) = watch::channel(ConnectionState::default());
+
+        tokio::spawn(connection_event_loop(
+            control_rx,
+            dispatcher,
+            config,
+            rtt.clone(),
+            dedup.clone(),
+            state_tx,
+        ));
+
+        Self {
+            control_tx,
+            rtt,
+            request_timeout,
+            inbound_capacity,
+            batch_concurrency,
+            dedup,
+            rate_limiter,
+            retry_policy,
+            state,
+        }
+    }
 
-        self.request(request);
+    /// Returns the receiver of the connection state updates. The current state
+    /// is available with [`watch::Receiver::borrow()`].
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Returns `true` if the client is currently connected to the Relay.
+    pub fn is_connected(&self) -> bool {
+        self.state.borrow().is_connected()
+    }
+
+    /// Returns the most recent websocket round-trip time measured by the
+    /// heartbeat. Always `None` if the heartbeat is disabled in the
+    /// [`ClientConfig`].
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        self.rtt.get()
+    }
 
-        response
+    pub fn create_topic(&self, topic: Topic) -> ResponseFuture<CreateTopic> {
+        self.send(CreateTopic { topic })
     }
 
     pub fn propose_session(
@@ -172,16 +354,12 @@ impl Client {
         attestation: impl Into<Option<Arc<str>>>,
         analytics: Option<AnalyticsData>,
     ) -> ResponseFuture<ProposeSession> {
-        let (request, response) = create_request(ProposeSession {
+        self.send(ProposeSession {
             pairing_topic,
             session_proposal: session_proposal.into(),
             attestation: attestation.into(),
             analytics: analytics.map(Into::into),
-        });
-
-        self.request(request);
-
-        response
+        })
     }
 
     pub fn approve_session(
@@ -193,18 +371,14 @@ impl Client {
         properties: SessionProperties,
         analytics: Option<AnalyticsData>,
     ) -> ResponseFuture<ApproveSession> {
-        let (request, response) = create_request(ApproveSession {
+        self.send(ApproveSession {
             pairing_topic,
             session_topic,
             session_proposal_response: session_proposal_response.into(),
             session_settlement_request: session_settlement_request.into(),
             properties: Arc::new(properties),
             analytics: analytics.map(Into::into),
-        });
-
-        self.request(request);
-
-        response
+        })
     }
 
     /// Publishes a message over the network on given topic.
@@ -217,7 +391,7 @@ impl Client {
         ttl: Duration,
         prompt: bool,
     ) -> EmptyResponseFuture<Publish> {
-        let (request, response) = create_request(Publish {
+        EmptyResponseFuture::new(self.send(Publish {
             topic,
             message: message.into(),
             attestation: attestation.into(),
@@ -225,21 +399,43 @@ impl Client {
             tag,
             prompt,
             analytics: None,
-        });
-
-        self.request(request);
-
-        EmptyResponseFuture::new(response)
+        }))
     }
 
     /// Subscribes on topic to receive messages. The request is resolved
     /// optimistically as soon as the relay receives it.
     pub fn subscribe(&self, topic: Topic) -> ResponseFuture<Subscribe> {
-        let (request, response) = create_request(Subscribe { topic });
-
-        self.request(request);
+        self.send(Subscribe { topic })
+    }
 
-        response
+    /// Subscribes on topic, returning a [`SubscriptionStream`] of the messages
+    /// published to the topic. The messages of the topic are not delivered to
+    /// the [`ConnectionHandler`] while the stream is alive.
+    ///
+    /// The messages that arrive before the subscription is confirmed are
+    /// delivered to the [`ConnectionHandler`].
+    ///
+    /// Dropping the stream unsubscribes from the topic, unless the topic has
+    /// been subscribed to again, either by another stream or directly.
+    ///
+    /// See [`SubscriptionStream`] for how the messages are acknowledged, and
+    /// rejected once its buffer is full.
+    pub async fn subscribe_stream(&self, topic: Topic) -> SubscriptionResult<SubscriptionStream> {
+        self.subscribe(topic.clone()).await?;
+
+        // Only route the messages once subscribed, so that a failed subscription
+        // doesn't unsubscribe from the topic.
+        let (tx, rx) = mpsc::channel(self.inbound_capacity.max(1));
+
+        self.control_tx
+            .send(ConnectionControl::Route {
+                topic: topic.clone(),
+                tx,
+            })
+            .await
+            .map_err(|_| Error::Client(ClientError::ChannelClosed))?;
+
+        Ok(SubscriptionStream::new(topic, rx))
     }
 
     /// Subscribes on topic to receive messages. The request is resolved only
@@ -247,29 +443,17 @@ impl Client {
     /// Note: This function is experimental and will likely be removed in the
     /// future.
     pub fn subscribe_blocking(&self, topic: Topic) -> ResponseFuture<SubscribeBlocking> {
-        let (request, response) = create_request(SubscribeBlocking { topic });
-
-        self.request(request);
-
-        response
+        self.send(SubscribeBlocking { topic })
     }
 
     /// Unsubscribes from a topic.
     pub fn unsubscribe(&self, topic: Topic) -> EmptyResponseFuture<Unsubscribe> {
-        let (request, response) = create_request(Unsubscribe { topic });
-
-        self.request(request);
-
-        EmptyResponseFuture::new(response)
+        EmptyResponseFuture::new(self.send(Unsubscribe { topic }))
     }
 
     /// Fetch mailbox messages for a specific topic.
     pub fn fetch(&self, topic: Topic) -> ResponseFuture<FetchMessages> {
-        let (request, response) = create_request(FetchMessages { topic });
-
-        self.request(request);
-
-        response
+        self.send(FetchMessages { topic })
     }
 
     /// Fetch mailbox messages for a specific topic. Returns a [`Stream`].
@@ -279,14 +463,32 @@ impl Client {
 
     /// Subscribes on multiple topics to receive messages. The request is
     /// resolved optimistically as soon as the relay receives it.
-    pub fn batch_subscribe(&self, topics: impl Into<Vec<Topic>>) -> ResponseFuture<BatchSubscribe> {
-        let (request, response) = create_request(BatchSubscribe {
-            topics: topics.into(),
+    ///
+    /// Batches exceeding [`MAX_SUBSCRIPTION_BATCH_SIZE`] are split into chunks
+    /// sent with bounded concurrency (see [`ClientConfig::batch_concurrency`]).
+    /// The subscription IDs are returned in the order of the topics. This also
+    /// applies to the other batch methods.
+    ///
+    /// Fails with [`ClientError::InvalidBatchResponse`] if the response to a
+    /// chunk doesn't have one ID per topic.
+    pub fn batch_subscribe(
+        &self,
+        topics: impl Into<Vec<Topic>>,
+    ) -> impl Future<Output = BatchResult<BatchSubscribe>> {
+        let topics = topics.into();
+        let len = topics.len();
+        let response = self.send_chunked(topics, MAX_SUBSCRIPTION_BATCH_SIZE, |topics| {
+            BatchSubscribe { topics }
         });
 
-        self.request(request);
-
-        response
+        async move {
+            let ids = response.await?;
+            Ok(crate::batch::join_chunks(
+                ids,
+                len,
+                MAX_SUBSCRIPTION_BATCH_SIZE,
+            )?)
+        }
     }
 
     /// Subscribes on multiple topics to receive messages. The request is
@@ -297,18 +499,21 @@ impl Client {
         &self,
         topics: impl Into<Vec<Topic>>,
     ) -> impl Future<Output = SubscriptionResult<Vec<SubscriptionResult<SubscriptionId>>>> {
-        let (request, response) = create_request(BatchSubscribeBlocking {
-            topics: topics.into(),
+        let topics = topics.into();
+        let len = topics.len();
+        let response = self.send_chunked(topics, MAX_SUBSCRIPTION_BATCH_SIZE, |topics| {
+            BatchSubscribeBlocking { topics }
         });
 
-        self.request(request);
-
         async move {
-            Ok(response
-                .await?
-                .into_iter()
-                .map(crate::convert_subscription_result)
-                .collect())
+            let results = response.await?;
+
+            Ok(
+                crate::batch::join_chunks(results, len, MAX_SUBSCRIPTION_BATCH_SIZE)?
+                    .into_iter()
+                    .map(crate::convert_subscription_result)
+                    .collect(),
+            )
         }
     }
 
@@ -316,49 +521,69 @@ impl Client {
     pub fn batch_unsubscribe(
         &self,
         subscriptions: impl Into<Vec<Unsubscribe>>,
-    ) -> EmptyResponseFuture<BatchUnsubscribe> {
-        let (request, response) = create_request(BatchUnsubscribe {
-            subscriptions: subscriptions.into(),
-        });
-
-        self.request(request);
-
-        EmptyResponseFuture::new(response)
+    ) -> impl Future<Output = SubscriptionResult<()>> {
+        let response = self.send_chunked(
+            subscriptions.into(),
+            MAX_SUBSCRIPTION_BATCH_SIZE,
+            |subscriptions| BatchUnsubscribe { subscriptions },
+        );
+
+        async move { response.await.map(|_| ()) }
     }
 
     /// Fetch mailbox messages for multiple topics.
-    pub fn batch_fetch(&self, topics: impl Into<Vec<Topic>>) -> ResponseFuture<BatchFetchMessages> {
-        let (request, response) = create_request(BatchFetchMessages {
-            topics: topics.into(),
+    pub fn batch_fetch(
+        &self,
+        topics: impl Into<Vec<Topic>>,
+    ) -> impl Future<Output = BatchResult<BatchFetchMessages>> {
+        let response = self.send_chunked(topics.into(), MAX_FETCH_BATCH_SIZE, |topics| {
+            BatchFetchMessages { topics }
         });
 
-        self.request(request);
-
-        response
+        async move { Ok(crate::batch::merge_fetch_responses(response.await?)) }
     }
 
     /// Acknowledge receipt of messages from a subscribed client.
-    pub async fn batch_receive(
+    pub fn batch_receive(
         &self,
         receipts: impl Into<Vec<Receipt>>,
-    ) -> ResponseFuture<BatchReceiveMessages> {
-        let (request, response) = create_request(BatchReceiveMessages {
-            receipts: receipts.into(),
+    ) -> impl Future<Output = BatchResult<BatchReceiveMessages>> {
+        let response = self.send_chunked(receipts.into(), MAX_RECEIVE_BATCH_SIZE, |receipts| {
+            BatchReceiveMessages { receipts }
         });
 
-        self.request(request);
+        async move { Ok(response.await?.into_iter().all(|result| result)) }
+    }
 
-        response
+    /// Marks the message as processed, queueing its receipt to be sent with
+    /// `irn_batchReceive` according to the [`ClientConfig::receipts`]
+    /// aggregation. Failed deliveries are reported to the handler as outbound
+    /// errors.
+    ///
+    /// The receipts marked while the client is shutting down are dropped.
+    pub async fn mark_processed(&self, receipt: Receipt) -> Result<(), ClientError> {
+        self.control_tx
+            .send(ConnectionControl::Receipt(receipt))
+            .await
+            .map_err(|_| ClientError::ChannelClosed)
     }
 
     /// Opens a connection to the Relay.
+    ///
+    /// If the client has a [`ReconnectPolicy`] configured, the options are
+    /// retained and used to restore the connection if it's lost.
     pub async fn connect(&self, opts: &ConnectionOptions) -> Result<(), ClientError> {
         let (tx, rx) = oneshot::channel();
         let request = opts.as_ws_request()?;
 
         if self
             .control_tx
-            .send(ConnectionControl::Connect { request, tx })
+            .send(ConnectionControl::Connect {
+                opts: Box::new(opts.clone()),
+                request,
+                tx,
+            })
+            .await
             .is_ok()
         {
             rx.await.map_err(|_| ClientError::ChannelClosed)?
@@ -374,6 +599,7 @@ impl Client {
         if self
             .control_tx
             .send(ConnectionControl::Disconnect { tx })
+            .await
             .is_ok()
         {
             rx.await.map_err(|_| ClientError::ChannelClosed)?
@@ -382,16 +608,223 @@ impl Client {
         }
     }
 
-    pub(crate) fn request(&self, request: OutboundRequest) {
-        if let Err(err) = self
+    /// Gracefully closes the Relay connection.
+    ///
+    /// Stops accepting new requests, which fail with
+    /// [`ClientError::ShuttingDown`], and waits up to `timeout` for the queued
+    /// outbound requests to be sent and the in-flight requests to receive their
+    /// responses. The connection is then closed with the close frame. The
+    /// requests still outstanding at the deadline fail with
+    /// [`WebsocketClientError::ConnectionClosed`], and the requests sent after
+    /// the shutdown completes fail with [`ClientError::ChannelClosed`].
+    ///
+    /// The connection is not restored while shutting down.
+    pub async fn shutdown(
+        &self,
+        timeout: Duration,
+        frame: impl Into<Option<CloseFrame>>,
+    ) -> Result<(), ClientError> {
+        let (tx, rx) = oneshot::channel();
+
+        if self
             .control_tx
-            .send(ConnectionControl::OutboundRequest(request))
+            .send(ConnectionControl::Shutdown {
+                frame: frame.into(),
+                timeout,
+                tx,
+            })
+            .await
+            .is_ok()
         {
-            let ConnectionControl::OutboundRequest(request) = err.0 else {
-                unreachable!();
+            rx.await.map_err(|_| ClientError::ChannelClosed)?
+        } else {
+            Err(ClientError::ChannelClosed)
+        }
+    }
+
+    /// Sends the request and returns a future that resolves with the response.
+    ///
+    /// If the request queue is full, or the rate limiter requires waiting for a
+    /// permit, the request is sent from a background task once it can proceed,
+    /// whether or not the returned future is polled. Use
+    /// [`Client::try_send()`] to be notified of the full queue instead. The
+    /// default request timeout from the [`ClientConfig`] is applied to the
+    /// returned future.
+    ///
+    /// The requests retried according to the [`ClientConfig::retry`] policy are
+    /// sent from a background task, whether or not the returned future is
+    /// polled.
+    pub fn send<T>(&self, data: T) -> ResponseFuture<T>
+    where
+        T: ServiceRequest,
+    {
+        let (request, response) = create_request(data);
+
+        if let Some(policy) = self
+            .retry_policy
+            .as_ref()
+            .filter(|policy| policy.applies_to(&request.params))
+        {
+            let client = self.clone();
+            let policy = policy.clone();
+
+            tokio::spawn(async move {
+                client.send_with_retry(request, &policy).await;
+            });
+
+            return self.apply_timeout(response);
+        }
+
+        if let Some(limiter) = &self.rate_limiter {
+            return match limiter.try_acquire(MethodClass::of(&request.params)) {
+                Ok(()) => self.send_control(ConnectionControl::OutboundRequest(request), response),
+
+                Err(err) if limiter.mode() == RateLimitMode::FailFast => {
+                    ConnectionControl::OutboundRequest(request).fail(err);
+                    self.apply_timeout(response)
+                }
+
+                Err(_) => {
+                    let client = self.clone();
+
+                    tokio::spawn(async move {
+                        client.send_outbound(request).await;
+                    });
+
+                    self.apply_timeout(response)
+                }
             };
+        }
+
+        self.send_control(ConnectionControl::OutboundRequest(request), response)
+    }
+
+    /// Sends the request, waiting for the rate limiter permit and the request
+    /// queue capacity.
+    async fn send_outbound(&self, request: OutboundRequest) {
+        let class = MethodClass::of(&request.params);
+        let control = ConnectionControl::OutboundRequest(request);
+
+        if let Some(limiter) = &self.rate_limiter {
+            if let Err(err) = limiter.acquire(class).await {
+                control.fail(err);
+                return;
+            }
+        }
+
+        if let Err(err) = self.control_tx.send(control).await {
+            err.0.fail(ClientError::ChannelClosed);
+        }
+    }
+
+    /// Sends the request, repeating the failed attempts according to the retry
+    /// policy, and responds with the result of the last attempt.
+    async fn send_with_retry(&self, request: OutboundRequest, policy: &RetryPolicy) {
+        let OutboundRequest { params, tx } = request;
+        let mut attempt = 1;
+
+        loop {
+            let (attempt_tx, attempt_rx) = oneshot::channel();
+
+            self.send_outbound(OutboundRequest::new(params.clone(), attempt_tx))
+                .await;
+
+            let result = attempt_rx.await.unwrap_or(Err(ClientError::ChannelClosed));
+
+            if let Err(err) = &result {
+                if let Some(delay) = policy.retry_delay(attempt, err) {
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    continue;
+                }
+            }
+
+            tx.send(result).ok();
+            return;
+        }
+    }
+
+    /// Queues the outbound request, waiting for the queue capacity in a
+    /// background task if the queue is full.
+    fn send_control<T>(
+        &self,
+        control: ConnectionControl,
+        response: ResponseFuture<T>,
+    ) -> ResponseFuture<T> {
+        let response = match self.control_tx.try_send(control) {
+            Ok(()) => response,
+
+            Err(TrySendError::Full(control)) => {
+                let control_tx = self.control_tx.clone();
+
+                tokio::spawn(async move {
+                    if let Err(err) = control_tx.send(control).await {
+                        err.0.fail(ClientError::ChannelClosed);
+                    }
+                });
+
+                response
+            }
+
+            Err(TrySendError::Closed(control)) => {
+                control.fail(ClientError::ChannelClosed);
+                response
+            }
+        };
+
+        self.apply_timeout(response)
+    }
+
+    /// Sends the batch request in chunks of up to `chunk_size` items, with at
+    /// most [`ClientConfig::batch_concurrency`] chunks in flight. Resolves with
+    /// the chunk responses in order.
+    fn send_chunked<T, I>(
+        &self,
+        items: Vec<I>,
+        chunk_size: usize,
+        into_request: impl Fn(Vec<I>) -> T + Send + 'static,
+    ) -> impl Future<Output = Result<Vec<T::Response>, Error<T::Error>>> + Send + 'static
+    where
+        T: ServiceRequest,
+        I: Send + 'static,
+    {
+        let client = self.clone();
+        let requests = crate::batch::chunks(items, chunk_size)
+            .map(move |chunk| client.send(into_request(chunk)));
+
+        crate::batch::send_chunks(requests, self.batch_concurrency)
+    }
+
+    /// Sends the request and returns a future that resolves with the response.
+    /// Fails with [`ClientError::QueueFull`] if the request queue is full, or
+    /// with [`ClientError::RateLimited`] if the rate limiter has no permits
+    /// available, regardless of the [`RateLimitMode`].
+    pub fn try_send<T>(&self, data: T) -> Result<ResponseFuture<T>, ClientError>
+    where
+        T: ServiceRequest,
+    {
+        let (request, response) = create_request(data);
+
+        // Reserve the queue capacity first, so that the rejected requests don't
+        // consume the rate limit budget.
+        let permit = self.control_tx.try_reserve().map_err(|err| match err {
+            TrySendError::Full(_) => ClientError::QueueFull,
+            TrySendError::Closed(_) => ClientError::ChannelClosed,
+        })?;
+
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.try_acquire(MethodClass::of(&request.params))?;
+        }
+
+        permit.send(ConnectionControl::OutboundRequest(request));
+
+        Ok(self.apply_timeout(response))
+    }
 
-            request.tx.send(Err(ClientError::ChannelClosed)).ok();
+    fn apply_timeout<T>(&self, response: ResponseFuture<T>) -> ResponseFuture<T> {
+        match self.request_timeout {
+            Some(timeout) => response.timeout(timeout),
+            None => response,
         }
     }
 }
@@ -0,0 +1,171 @@
+use {
+    super::inbound::InboundRequest,
+    crate::ClientError,
+    relay_rpc::{
+        domain::MessageId,
+        rpc::{GenericError, Subscription},
+    },
+    std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    tokio::sync::Notify,
+};
+
+/// Default time the application has to acknowledge an inbound message before
+/// the [`AckConfig::on_timeout`] action is applied.
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Action applied to the messages that haven't been acknowledged in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AckTimeoutAction {
+    /// Report the message as successfully processed.
+    Ack,
+
+    /// Report the message processing failure, so that it can be redelivered by
+    /// the Relay.
+    #[default]
+    Nack,
+}
+
+/// Manual acknowledgement mode configuration.
+///
+/// When enabled, the inbound subscription messages are not acknowledged
+/// automatically after being delivered to the application. Instead, the
+/// application receives an [`AckHandle`] for each message and has to call
+/// [`AckHandle::ack()`] or [`AckHandle::nack()`] once the message is processed.
+#[derive(Debug, Clone)]
+pub struct AckConfig {
+    /// Time the application has to acknowledge the message.
+    pub timeout: Duration,
+
+    /// Action applied to the message if it's not acknowledged in time.
+    pub on_timeout: AckTimeoutAction,
+}
+
+impl Default for AckConfig {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_ACK_TIMEOUT,
+            on_timeout: AckTimeoutAction::default(),
+        }
+    }
+}
+
+impl AckConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_timeout_action(mut self, action: AckTimeoutAction) -> Self {
+        self.on_timeout = action;
+        self
+    }
+}
+
+#[derive(Debug)]
+struct AckState {
+    request: Mutex<Option<InboundRequest<Subscription>>>,
+    resolved: Notify,
+}
+
+/// Handle used to acknowledge an inbound subscription message in the manual
+/// acknowledgement mode (see [`ClientConfig::manual_ack`]).
+///
+/// The handle is cheap to clone. The message is acknowledged only once: the
+/// first [`AckHandle::ack()`] or [`AckHandle::nack()`] call wins, and the
+/// subsequent calls have no effect. If none of the clones acknowledges the
+/// message within [`AckConfig::timeout`], the [`AckConfig::on_timeout`] action
+/// is applied.
+///
+/// [`ClientConfig::manual_ack`]: crate::websocket::ClientConfig::manual_ack
+#[derive(Debug, Clone)]
+pub struct AckHandle {
+    id: MessageId,
+    state: Arc<AckState>,
+}
+
+impl AckHandle {
+    pub(super) fn new(request: InboundRequest<Subscription>, config: &AckConfig) -> Self {
+        let handle = Self {
+            id: request.id(),
+            state: Arc::new(AckState {
+                request: Mutex::new(Some(request)),
+                resolved: Notify::new(),
+            }),
+        };
+
+        tokio::spawn({
+            let handle = handle.clone();
+            let timeout = config.timeout;
+            let action = config.on_timeout;
+
+            async move {
+                tokio::select! {
+                    _ = tokio::time::sleep(timeout) => {
+                        let response = match action {
+                            AckTimeoutAction::Ack => Ok(true),
+                            AckTimeoutAction::Nack => Err(GenericError::Unknown),
+                        };
+
+                        handle.respond(response).ok();
+                    }
+
+                    _ = handle.state.resolved.notified() => {}
+                }
+            }
+        });
+
+        handle
+    }
+
+    /// Creates a handle of the message that has already been acknowledged.
+    pub(super) fn resolved(id: MessageId) -> Self {
+        Self {
+            id,
+            state: Arc::new(AckState {
+                request: Mutex::new(None),
+                resolved: Notify::new(),
+            }),
+        }
+    }
+
+    /// ID of the message being acknowledged.
+    pub fn message_id(&self) -> MessageId {
+        self.id
+    }
+
+    /// Returns `true` if the message has already been acknowledged, either by
+    /// the application or due to the timeout.
+    pub fn is_resolved(&self) -> bool {
+        self.lock().is_none()
+    }
+
+    /// Reports the message as successfully processed.
+    pub fn ack(&self) -> Result<(), ClientError> {
+        self.respond(Ok(true))
+    }
+
+    /// Reports the message processing failure.
+    pub fn nack(&self, err: GenericError) -> Result<(), ClientError> {
+        self.respond(Err(err))
+    }
+
+    fn respond(&self, response: Result<bool, GenericError>) -> Result<(), ClientError> {
+        let Some(request) = self.lock().take() else {
+            return Ok(());
+        };
+
+        self.state.resolved.notify_one();
+        request.respond(response)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<InboundRequest<Subscription>>> {
+        crate::lock(&self.state.request)
+    }
+}
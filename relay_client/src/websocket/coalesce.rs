@@ -0,0 +1,281 @@
+use {
+    super::outbound::OutboundRequest,
+    crate::ClientError,
+    futures_util::future::BoxFuture,
+    relay_rpc::{
+        domain::Topic,
+        rpc::{BatchSubscribe, BatchUnsubscribe, Params, Unsubscribe, MAX_SUBSCRIPTION_BATCH_SIZE},
+    },
+    std::{
+        collections::{hash_map::Entry, HashMap},
+        pin::Pin,
+        sync::Arc,
+        time::Duration,
+    },
+    tokio::{
+        sync::oneshot,
+        time::{sleep, Sleep},
+    },
+};
+
+type ResponseSender = oneshot::Sender<Result<serde_json::Value, ClientError>>;
+
+/// Gathers the `irn_subscribe` and `irn_unsubscribe` requests made within a
+/// time window into `irn_batchSubscribe` and `irn_batchUnsubscribe` requests.
+///
+/// Only the requests of the same kind are coalesced, and the queued requests
+/// are flushed whenever the kind changes, so that the subscriptions and
+/// unsubscriptions are sent in the order they were made. The requests for the
+/// same topic are sent once, and share the response.
+pub(super) struct Coalescer {
+    window: Duration,
+    queue: Vec<(Params, ResponseSender)>,
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+/// Batched request, along with the future distributing its response to the
+/// original requests.
+pub(super) type Batch = (OutboundRequest, BoxFuture<'static, Result<(), ClientError>>);
+
+impl Coalescer {
+    pub(super) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            queue: Vec::new(),
+            timer: None,
+        }
+    }
+
+    /// Returns `true` if the request can be coalesced.
+    pub(super) fn accepts(&self, request: &OutboundRequest) -> bool {
+        matches!(
+            request.params,
+            Params::Subscribe(_) | Params::Unsubscribe(_)
+        )
+    }
+
+    /// Queues the request, returning the batches that need to be sent right
+    /// away.
+    pub(super) fn push(&mut self, request: OutboundRequest) -> Vec<Batch> {
+        let mut batches = Vec::new();
+
+        let same_kind = self.queue.first().is_none_or(|(params, _)| {
+            std::mem::discriminant(params) == std::mem::discriminant(&request.params)
+        });
+
+        if !same_kind {
+            batches.extend(self.flush());
+        }
+
+        self.queue.push((request.params, request.tx));
+
+        if self.queue.len() >= MAX_SUBSCRIPTION_BATCH_SIZE {
+            batches.extend(self.flush());
+        } else if self.timer.is_none() {
+            self.timer = Some(Box::pin(sleep(self.window)));
+        }
+
+        batches
+    }
+
+    /// Waits for the coalescing window to close. Pending if there are no queued
+    /// requests.
+    pub(super) async fn wait(&mut self) {
+        match &mut self.timer {
+            Some(timer) => timer.await,
+            None => std::future::pending().await,
+        }
+
+        self.timer = None;
+    }
+
+    /// Takes all of the queued requests.
+    pub(super) fn flush(&mut self) -> Option<Batch> {
+        self.timer = None;
+
+        let queue = std::mem::take(&mut self.queue);
+
+        match queue.first() {
+            Some((Params::Subscribe(_), _)) => subscribe_batch(queue),
+            Some((Params::Unsubscribe(_), _)) => unsubscribe_batch(queue),
+            _ => None,
+        }
+    }
+}
+
+fn subscribe_batch(requests: Vec<(Params, ResponseSender)>) -> Option<Batch> {
+    let (topics, senders): (Vec<_>, Vec<_>) =
+        group_by_topic(requests, |params| Some(params.into_subscribe().ok()?.topic))
+            .into_iter()
+            .unzip();
+
+    if topics.is_empty() {
+        return None;
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let request = OutboundRequest::new(Params::BatchSubscribe(BatchSubscribe { topics }), tx);
+
+    let distribute = async move {
+        let ids = match rx.await {
+            Ok(Ok(value)) => serde_json::from_value::<Vec<serde_json::Value>>(value)
+                .map_err(ClientError::Deserialization),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(ClientError::ChannelClosed),
+        };
+
+        match ids {
+            Ok(ids) if ids.len() == senders.len() => {
+                for (senders, id) in senders.into_iter().zip(ids) {
+                    for tx in senders {
+                        tx.send(Ok(id.clone())).ok();
+                    }
+                }
+
+                Ok(())
+            }
+
+            Ok(ids) => {
+                let (expected, actual) = (senders.len(), ids.len());
+
+                fail_all(senders, ClientError::InvalidBatchResponse {
+                    expected,
+                    actual,
+                });
+                Err(ClientError::InvalidBatchResponse { expected, actual })
+            }
+
+            Err(err) => {
+                fail_all(senders, err);
+                Ok(())
+            }
+        }
+    };
+
+    Some((request, Box::pin(distribute)))
+}
+
+fn unsubscribe_batch(requests: Vec<(Params, ResponseSender)>) -> Option<Batch> {
+    let (subscriptions, senders): (Vec<_>, Vec<_>) = group_by_topic(requests, |params| {
+        Some(params.into_unsubscribe().ok()?.topic)
+    })
+    .into_iter()
+    .map(|(topic, senders)| (Unsubscribe { topic }, senders))
+    .unzip();
+
+    if subscriptions.is_empty() {
+        return None;
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let request = OutboundRequest::new(
+        Params::BatchUnsubscribe(BatchUnsubscribe { subscriptions }),
+        tx,
+    );
+
+    let distribute = async move {
+        match rx.await {
+            Ok(Ok(value)) => {
+                for tx in senders.into_iter().flatten() {
+                    tx.send(Ok(value.clone())).ok();
+                }
+            }
+
+            Ok(Err(err)) => fail_all(senders, err),
+
+            Err(_) => fail_all(senders, ClientError::ChannelClosed),
+        }
+
+        Ok(())
+    };
+
+    Some((request, Box::pin(distribute)))
+}
+
+/// Groups the senders of the requests that are still awaited by the topic of
+/// the request, keeping the order in which the topics were first requested.
+fn group_by_topic(
+    requests: Vec<(Params, ResponseSender)>,
+    topic: fn(Params) -> Option<Topic>,
+) -> Vec<(Topic, Vec<ResponseSender>)> {
+    let mut groups: Vec<(Topic, Vec<ResponseSender>)> = Vec::new();
+    let mut index: HashMap<Topic, usize> = HashMap::new();
+
+    for (params, tx) in requests {
+        if tx.is_closed() {
+            continue;
+        }
+
+        let Some(topic) = topic(params) else {
+            continue;
+        };
+
+        match index.entry(topic) {
+            Entry::Occupied(entry) => {
+                if let Some((_, senders)) = groups.get_mut(*entry.get()) {
+                    senders.push(tx);
+                }
+            }
+
+            Entry::Vacant(entry) => {
+                groups.push((entry.key().clone(), vec![tx]));
+                entry.insert(groups.len() - 1);
+            }
+        }
+    }
+
+    groups
+}
+
+/// Fails each of the coalesced requests with the batch request error, shared
+/// as [`ClientError::BatchFailed`].
+fn fail_all(senders: Vec<Vec<ResponseSender>>, err: ClientError) {
+    let err = Arc::new(err);
+
+    for tx in senders.into_iter().flatten() {
+        tx.send(Err(ClientError::BatchFailed(err.clone()))).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, relay_rpc::rpc::Subscribe};
+
+    #[tokio::test]
+    async fn short_batch_response() {
+        let mut coalescer = Coalescer::new(Duration::from_secs(1));
+        let mut responses = Vec::new();
+
+        for _ in 0..2 {
+            let (tx, rx) = oneshot::channel();
+            let params = Params::Subscribe(Subscribe {
+                topic: Topic::generate(),
+            });
+
+            assert!(coalescer.push(OutboundRequest::new(params, tx)).is_empty());
+            responses.push(rx);
+        }
+
+        let (request, distribute) = coalescer.flush().unwrap();
+        request.tx.send(Ok(serde_json::json!(["id"]))).ok();
+
+        assert!(matches!(
+            distribute.await,
+            Err(ClientError::InvalidBatchResponse {
+                expected: 2,
+                actual: 1
+            })
+        ));
+
+        for rx in responses {
+            let Err(ClientError::BatchFailed(err)) = rx.await.unwrap() else {
+                panic!("expected batch failure");
+            };
+
+            assert!(matches!(*err, ClientError::InvalidBatchResponse {
+                expected: 2,
+                actual: 1
+            }));
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use {
+    super::{
+        AckConfig,
+        DedupConfig,
+        OfflineQueueConfig,
+        ReceiptConfig,
+        Transport,
+        TungsteniteTransport,
+        DEFAULT_OUTBOUND_CAPACITY,
+    },
+    crate::{
+        backoff::Backoff,
+        rate_limit::RateLimiter,
+        retry::RetryPolicy,
+        store::SubscriptionStore,
+    },
+    std::{sync::Arc, time::Duration},
+};
+
+pub use crate::batch::DEFAULT_BATCH_CONCURRENCY;
+
+/// Default capacity of the queue between the [`Client`] handles and the
+/// connection.
+///
+/// [`Client`]: crate::websocket::Client
+pub const DEFAULT_REQUEST_CAPACITY: usize = 1024;
+
+/// Default capacity of the queue delivering the inbound events to the
+/// [`ConnectionHandler`].
+///
+/// [`ConnectionHandler`]: crate::websocket::ConnectionHandler
+pub const DEFAULT_INBOUND_CAPACITY: usize = 1024;
+
+/// Policy for automatically restoring a dropped Relay connection.
+///
+/// When the connection is closed by anything other than
+/// [`Client::disconnect()`][crate::websocket::Client::disconnect], the client
+/// waits for the delay produced by the [`Backoff`], reconnects using the same
+/// [`ConnectionOptions`][crate::ConnectionOptions], and re-subscribes all of
+/// the topics it was subscribed to.
+#[derive(Debug, Clone, Default)]
+pub struct ReconnectPolicy {
+    /// Delay between the reconnection attempts.
+    pub backoff: Backoff,
+
+    /// The maximum number of consecutive reconnection attempts. Unlimited if
+    /// `None`.
+    pub max_attempts: Option<u32>,
+}
+
+impl ReconnectPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: impl Into<Option<u32>>) -> Self {
+        self.max_attempts = max_attempts.into();
+        self
+    }
+
+    pub(super) fn should_retry(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt < max)
+    }
+}
+
+/// Websocket ping/pong heartbeat configuration.
+///
+/// Pings are sent on the specified interval. If a pong is not received within
+/// the timeout, the connection is considered dead and is closed. This detects
+/// half-open connections that would otherwise appear connected.
+#[derive(Debug, Clone)]
+pub struct HeartbeatConfig {
+    /// Interval between the pings.
+    pub interval: Duration,
+
+    /// Time to wait for the pong before closing the connection.
+    pub timeout: Duration,
+}
+
+impl HeartbeatConfig {
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Self { interval, timeout }
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Websocket [`Client`][crate::websocket::Client] configuration.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Automatic reconnection policy. Reconnection is disabled if `None`.
+    pub reconnect: Option<ReconnectPolicy>,
+
+    /// Websocket ping/pong heartbeat. Disabled if `None`.
+    pub heartbeat: Option<HeartbeatConfig>,
+
+    /// Default timeout applied to all requests made by the client. Can be
+    /// overridden per request with [`ResponseFuture::timeout()`]. Requests
+    /// wait for the response indefinitely if `None`.
+    ///
+    /// [`ResponseFuture::timeout()`]: crate::websocket::ResponseFuture::timeout
+    pub request_timeout: Option<Duration>,
+
+    /// Capacity of the queue between the [`Client`] handles and the
+    /// connection. When the queue is full, the requests wait for capacity, or
+    /// fail with [`ClientError::QueueFull`] if sent with
+    /// [`Client::try_send()`].
+    ///
+    /// [`Client`]: crate::websocket::Client
+    /// [`Client::try_send()`]: crate::websocket::Client::try_send
+    /// [`ClientError::QueueFull`]: crate::ClientError::QueueFull
+    pub request_capacity: usize,
+
+    /// Capacity of the queue of requests waiting to be written to the socket.
+    /// When the queue is full, the connection stops accepting requests from the
+    /// [`Client`] handles.
+    ///
+    /// [`Client`]: crate::websocket::Client
+    pub outbound_capacity: usize,
+
+    /// Capacity of the queue delivering the inbound messages and connection
+    /// events to the [`ConnectionHandler`], or to each of the
+    /// [`ClientEventStream`] consumers. When the queue is full, the connection
+    /// stops reading the inbound messages until the consumer catches up.
+    ///
+    /// Also the capacity of each [`SubscriptionStream`], which rejects the
+    /// messages instead once full, so that the Relay redelivers them.
+    ///
+    /// [`ConnectionHandler`]: crate::websocket::ConnectionHandler
+    /// [`ClientEventStream`]: crate::websocket::ClientEventStream
+    /// [`SubscriptionStream`]: crate::websocket::SubscriptionStream
+    pub inbound_capacity: usize,
+
+    /// Manual acknowledgement of the inbound subscription messages. If `None`,
+    /// the messages are acknowledged as soon as they're delivered to the
+    /// application.
+    ///
+    /// Also applies to the messages of the [`SubscriptionStream`]s, see
+    /// [`SubscriptionStream::with_ack()`].
+    ///
+    /// [`SubscriptionStream`]: crate::websocket::SubscriptionStream
+    /// [`SubscriptionStream::with_ack()`]: crate::websocket::SubscriptionStream::with_ack
+    pub manual_ack: Option<AckConfig>,
+
+    /// Connector used to open the websocket connections. Defaults to
+    /// [`TungsteniteTransport`].
+    pub transport: Arc<dyn Transport>,
+
+    /// Window for coalescing the `irn_subscribe` and `irn_unsubscribe`
+    /// requests made with [`Client::subscribe()`] and
+    /// [`Client::unsubscribe()`] into `irn_batchSubscribe` and
+    /// `irn_batchUnsubscribe` requests of up to [`MAX_SUBSCRIPTION_BATCH_SIZE`]
+    /// topics. Each request is sent individually if `None`.
+    ///
+    /// The requests for the same topic share the response, and a failed batch
+    /// request fails each of its requests with [`ClientError::BatchFailed`].
+    ///
+    /// [`ClientError::BatchFailed`]: crate::ClientError::BatchFailed
+    /// [`Client::subscribe()`]: crate::websocket::Client::subscribe
+    /// [`Client::unsubscribe()`]: crate::websocket::Client::unsubscribe
+    /// [`MAX_SUBSCRIPTION_BATCH_SIZE`]: relay_rpc::rpc::MAX_SUBSCRIPTION_BATCH_SIZE
+    pub subscription_batch_window: Option<Duration>,
+
+    /// The maximum number of chunks sent concurrently when a batch request
+    /// exceeds the Relay batch size limit and has to be split. Defaults to
+    /// [`DEFAULT_BATCH_CONCURRENCY`].
+    pub batch_concurrency: usize,
+
+    /// Buffering of the publish requests made while the client is
+    /// disconnected. The requests fail with [`NotConnected`] if `None`.
+    ///
+    /// [`NotConnected`]: crate::websocket::WebsocketClientError::NotConnected
+    pub offline_queue: Option<OfflineQueueConfig>,
+
+    /// Persistent record of the subscriptions. The stored topics are
+    /// re-subscribed each time the client connects. Disabled if `None`.
+    pub subscription_store: Option<Arc<dyn SubscriptionStore>>,
+
+    /// De-duplication of the inbound messages. Disabled if `None`.
+    pub dedup: Option<DedupConfig>,
+
+    /// Client-side rate limiter gating the outbound requests. Can be shared
+    /// with other clients. Disabled if `None`.
+    pub rate_limiter: Option<RateLimiter>,
+
+    /// Retry policy of the idempotent requests. Requests are not retried if
+    /// `None`. Note that the [`ClientConfig::request_timeout`] applies to all
+    /// of the attempts combined.
+    pub retry: Option<RetryPolicy>,
+
+    /// Aggregation of the receipts of the messages marked as processed with
+    /// [`Client::mark_processed()`] into `irn_batchReceive` requests. Each
+    /// receipt is sent individually if `None`.
+    ///
+    /// [`Client::mark_processed()`]: crate::websocket::Client::mark_processed
+    pub receipts: Option<ReceiptConfig>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            reconnect: None,
+            heartbeat: None,
+            request_timeout: None,
+            request_capacity: DEFAULT_REQUEST_CAPACITY,
+            outbound_capacity: DEFAULT_OUTBOUND_CAPACITY,
+            inbound_capacity: DEFAULT_INBOUND_CAPACITY,
+            manual_ack: None,
+            transport: Arc::new(TungsteniteTransport),
+            subscription_batch_window: None,
+            batch_concurrency: DEFAULT_BATCH_CONCURRENCY,
+            offline_queue: None,
+            subscription_store: None,
+            dedup: None,
+            rate_limiter: None,
+            retry: None,
+            receipts: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_reconnect(mut self, policy: impl Into<Option<ReconnectPolicy>>) -> Self {
+        self.reconnect = policy.into();
+        self
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: impl Into<Option<HeartbeatConfig>>) -> Self {
+        self.heartbeat = heartbeat.into();
+        self
+    }
+
+    pub fn with_request_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.request_timeout = timeout.into();
+        self
+    }
+
+    pub fn with_request_capacity(mut self, capacity: usize) -> Self {
+        self.request_capacity = capacity;
+        self
+    }
+
+    pub fn with_outbound_capacity(mut self, capacity: usize) -> Self {
+        self.outbound_capacity = capacity;
+        self
+    }
+
+    pub fn with_inbound_capacity(mut self, capacity: usize) -> Self {
+        self.inbound_capacity = capacity;
+        self
+    }
+
+    pub fn with_manual_ack(mut self, config: impl Into<Option<AckConfig>>) -> Self {
+        self.manual_ack = config.into();
+        self
+    }
+
+    pub fn with_transport(mut self, transport: impl Transport) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    pub fn with_subscription_batching(mut self, window: impl Into<Option<Duration>>) -> Self {
+        self.subscription_batch_window = window.into();
+        self
+    }
+
+    pub fn with_batch_concurrency(mut self, concurrency: usize) -> Self {
+        self.batch_concurrency = concurrency;
+        self
+    }
+
+    pub fn with_offline_queue(mut self, config: impl Into<Option<OfflineQueueConfig>>) -> Self {
+        self.offline_queue = config.into();
+        self
+    }
+
+    pub fn with_subscription_store(mut self, store: impl SubscriptionStore) -> Self {
+        self.subscription_store = Some(Arc::new(store));
+        self
+    }
+
+    pub fn with_dedup(mut self, config: impl Into<Option<DedupConfig>>) -> Self {
+        self.dedup = config.into();
+        self
+    }
+
+    pub fn with_rate_limiter(mut self, limiter: impl Into<Option<RateLimiter>>) -> Self {
+        self.rate_limiter = limiter.into();
+        self
+    }
+
+    pub fn with_retry_policy(mut self, policy: impl Into<Option<RetryPolicy>>) -> Self {
+        self.retry = policy.into();
+        self
+    }
+
+    pub fn with_receipt_batching(mut self, config: impl Into<Option<ReceiptConfig>>) -> Self {
+        self.receipts = config.into();
+        self
+    }
+}
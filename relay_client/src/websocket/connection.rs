@@ -1,26 +1,80 @@
 use {
     super::{
-        outbound::OutboundRequest,
-        stream::{create_stream, ClientStream},
-        ConnectionHandler,
+        coalesce::{Batch, Coalescer},
+        dedup::DedupFilter,
+        dispatch::{Dispatcher, HandlerEvent},
+        heartbeat::RoundTripTime,
+        offline::OfflineQueue,
+        outbound::{create_request, OutboundRequest},
+        persist::{StoreErrors, StoreWriter},
+        receipt::{ReceiptAggregator, ReceiptEvent},
+        stream::{create_stream_with_transport, ClientStream},
+        subscription::{RoutedMessage, Router},
+        AckHandle,
+        ClientConfig,
+        CloseFrame,
+        CloseReason,
+        ConnectionState,
+        HeartbeatConfig,
+        PublishedMessage,
         RawTransportError,
+        ReconnectPolicy,
+        Transport,
         WebsocketClientError,
     },
     crate::{
-        websocket::{stream::StreamEvent, PublishedMessage},
+        error::Error,
+        metrics,
+        trace,
+        websocket::stream::StreamEvent,
         ClientError,
+        ConnectionOptions,
         HttpRequest,
     },
-    futures_util::{stream::FusedStream, Stream, StreamExt},
+    futures_util::{
+        future::BoxFuture,
+        stream::{FusedStream, FuturesUnordered},
+        FutureExt,
+        Stream,
+        StreamExt,
+    },
+    relay_rpc::{
+        domain::{SubscriptionId, Topic},
+        rpc::{
+            error::ServiceError,
+            BatchReceiveMessages,
+            BatchSubscribe,
+            ErrorData,
+            Params,
+            Receipt,
+            SubscriptionResult,
+            Unsubscribe,
+            MAX_SUBSCRIPTION_BATCH_SIZE,
+        },
+    },
+    serde::Deserialize,
     std::{
+        collections::HashSet,
+        future::Future,
         pin::Pin,
+        sync::{Arc, Mutex},
         task::{Context, Poll},
+        time::Duration,
+    },
+    tokio::{
+        sync::{
+            mpsc::{Receiver, Sender},
+            oneshot,
+            watch,
+        },
+        time::{sleep, Sleep},
     },
-    tokio::sync::{mpsc::UnboundedReceiver, oneshot},
 };
 
 pub(super) enum ConnectionControl {
     Connect {
+        /// Retained for building the requests of the reconnection attempts.
+        opts: Box<ConnectionOptions>,
         request: HttpRequest<()>,
         tx: oneshot::Sender<Result<(), ClientError>>,
     },
@@ -29,82 +83,530 @@ pub(super) enum ConnectionControl {
         tx: oneshot::Sender<Result<(), ClientError>>,
     },
 
+    Shutdown {
+        frame: Option<CloseFrame>,
+        timeout: Duration,
+        tx: oneshot::Sender<Result<(), ClientError>>,
+    },
+
     OutboundRequest(OutboundRequest),
+
+    Receipt(Receipt),
+
+    Route {
+        topic: Topic,
+        tx: Sender<RoutedMessage>,
+    },
 }
 
-pub(super) async fn connection_event_loop<T>(
-    mut control_rx: UnboundedReceiver<ConnectionControl>,
-    mut handler: T,
-) where
-    T: ConnectionHandler,
-{
-    let mut conn = Connection::new();
+impl ConnectionControl {
+    /// Fails the control request with the provided error.
+    pub(super) fn fail(self, err: ClientError) {
+        match self {
+            Self::Connect { tx, .. } | Self::Disconnect { tx } | Self::Shutdown { tx, .. } => {
+                tx.send(Err(err)).ok();
+            }
+
+            Self::OutboundRequest(request) => {
+                request.tx.send(Err(err)).ok();
+            }
+
+            Self::Receipt(_) | Self::Route { .. } => {}
+        }
+    }
+}
+
+/// State of the automatic reconnection.
+struct Reconnect {
+    policy: ReconnectPolicy,
+    opts: Option<ConnectionOptions>,
+    attempt: u32,
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl Reconnect {
+    fn new(policy: ReconnectPolicy) -> Self {
+        Self {
+            policy,
+            opts: None,
+            attempt: 0,
+            timer: None,
+        }
+    }
+
+    fn reset(&mut self, opts: Option<ConnectionOptions>) {
+        self.opts = opts;
+        self.attempt = 0;
+        self.timer = None;
+    }
+
+    /// Schedules the next reconnection attempt, unless the policy doesn't allow
+    /// any more attempts. Returns `false` if the attempt is not scheduled.
+    async fn schedule(
+        &mut self,
+        dispatcher: &Dispatcher,
+        state: &watch::Sender<ConnectionState>,
+    ) -> bool {
+        if self.opts.is_none() || !self.policy.should_retry(self.attempt) {
+            self.reset(None);
+            return false;
+        }
+
+        self.attempt += 1;
+        metrics::reconnect();
+
+        let delay = self.policy.backoff.delay(self.attempt);
+        self.timer = Some(Box::pin(sleep(delay)));
+        state.send_replace(ConnectionState::Reconnecting(self.attempt));
+
+        dispatcher
+            .send(HandlerEvent::Reconnecting {
+                attempt: self.attempt,
+                delay,
+            })
+            .await;
+
+        true
+    }
+
+    async fn wait(&mut self) {
+        match &mut self.timer {
+            Some(timer) => timer.await,
+            None => std::future::pending().await,
+        }
+
+        self.timer = None;
+    }
+}
+
+/// State of the graceful shutdown.
+struct Shutdown {
+    frame: Option<CloseFrame>,
+    deadline: Pin<Box<Sleep>>,
+    expired: bool,
+    tx: oneshot::Sender<Result<(), ClientError>>,
+}
+
+async fn shutdown_timer(shutdown: &mut Option<Shutdown>) {
+    match shutdown {
+        Some(shutdown) => {
+            shutdown.deadline.as_mut().await;
+            shutdown.expired = true;
+        }
+
+        None => std::future::pending().await,
+    }
+}
+
+async fn reconnect_timer(reconnect: &mut Option<Reconnect>) {
+    match reconnect {
+        Some(reconnect) => reconnect.wait().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn coalesce_timer(coalescer: &mut Option<Coalescer>) {
+    match coalescer {
+        Some(coalescer) => coalescer.wait().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn receipt_event(receipts: &mut Option<ReceiptAggregator>) -> ReceiptEvent {
+    match receipts {
+        Some(receipts) => receipts.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn offline_timer(offline: &Option<OfflineQueue>) {
+    match offline {
+        Some(offline) => offline.wait_expired().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn store_error(errors: &mut Option<StoreErrors>) -> Option<ClientError> {
+    match errors {
+        Some(errors) => Some(ClientError::SubscriptionStore(errors.recv().await?)),
+        None => std::future::pending().await,
+    }
+}
+
+pub(super) async fn connection_event_loop(
+    mut control_rx: Receiver<ConnectionControl>,
+    dispatcher: Dispatcher,
+    config: ClientConfig,
+    rtt: RoundTripTime,
+    dedup: Option<DedupFilter>,
+    state: watch::Sender<ConnectionState>,
+) {
+    let (store, mut store_errors) = config.subscription_store.map(StoreWriter::spawn).unzip();
+
+    let mut conn = Connection::new(
+        config.heartbeat,
+        config.outbound_capacity,
+        rtt,
+        config.transport,
+        store,
+    );
+    let mut reconnect = config.reconnect.map(Reconnect::new);
+    let mut router = Router::new(config.manual_ack.clone(), dedup.clone());
+    let mut coalescer = config.subscription_batch_window.map(Coalescer::new);
+    let mut offline = config.offline_queue.map(OfflineQueue::new);
+    let mut receipts = config.receipts.map(ReceiptAggregator::new);
+    let mut pending = FuturesUnordered::new();
+    let mut shutdown: Option<Shutdown> = None;
+
+    // The reason the last connection was closed for, reported once the
+    // reconnection attempts are exhausted.
+    let mut close_reason = CloseReason(None);
 
     loop {
+        if let Some(drain) = &mut shutdown {
+            // The queued requests can't be sent once the connection is lost, since
+            // it's not restored while shutting down.
+            if !conn.is_connected() {
+                if let Some(offline) = &mut offline {
+                    offline.clear(|| ClientError::ShuttingDown);
+                }
+
+                if let Some(receipts) = &mut receipts {
+                    receipts.clear();
+                }
+            }
+
+            let drained = conn.is_drained()
+                && pending.is_empty()
+                && offline.as_ref().is_none_or(OfflineQueue::is_empty)
+                && receipts.as_ref().is_none_or(ReceiptAggregator::is_empty);
+
+            if drained || drain.expired {
+                let Shutdown { frame, tx, .. } = shutdown.take().unwrap_or_else(|| unreachable!());
+
+                // The handler has already been notified if the connection was lost
+                // while draining.
+                let result = if conn.is_connected() {
+                    let result = conn.disconnect(frame.clone()).await;
+                    dispatcher.send(HandlerEvent::Disconnected(frame)).await;
+                    result
+                } else {
+                    Ok(())
+                };
+
+                state.send_replace(ConnectionState::Disconnected);
+                tx.send(result).ok();
+                break;
+            }
+        }
+
         tokio::select! {
-            event = control_rx.recv() => {
+            // Only accept new requests if the outbound queue has capacity.
+            event = control_rx.recv(), if conn.has_capacity() => {
                 match event {
+                    Some(ConnectionControl::Route { .. }) if shutdown.is_some() => {}
+
+                    Some(event) if shutdown.is_some() => event.fail(ClientError::ShuttingDown),
+
                     Some(event) => match event {
-                        ConnectionControl::Connect { request, tx } => {
+                        ConnectionControl::Connect { opts, request, tx } => {
+                            state.send_replace(ConnectionState::Connecting);
+
                             let result = conn.connect(request).await;
 
+                            state.send_replace(if result.is_ok() {
+                                ConnectionState::Connected(chrono::Utc::now())
+                            } else {
+                                ConnectionState::Disconnected
+                            });
+
+                            if let Some(reconnect) = &mut reconnect {
+                                reconnect.reset(result.is_ok().then_some(*opts));
+                            }
+
                             if result.is_ok() {
-                                handler.connected();
+                                dispatcher.send(HandlerEvent::Connected).await;
+                                pending.extend(conn.restore().await);
+                                pending.extend(conn.flush(&mut offline));
+                                pending.extend(conn.send_receipts(&mut receipts));
                             }
 
                             tx.send(result).ok();
                         }
 
                         ConnectionControl::Disconnect { tx } => {
-                            tx.send(conn.disconnect().await).ok();
+                            if let Some(reconnect) = &mut reconnect {
+                                reconnect.reset(None);
+                            }
+
+                            // The stored topics are loaded again on the next connect.
+                            crate::lock(&conn.subscriptions).clear();
+                            conn.restored = false;
+
+                            tx.send(conn.disconnect(None).await).ok();
+                            state.send_replace(ConnectionState::Disconnected);
                         }
 
-                        ConnectionControl::OutboundRequest(request) => {
-                            conn.request(request);
+                        ConnectionControl::Shutdown { frame, timeout, tx } => {
+                            if let Some(reconnect) = &mut reconnect {
+                                reconnect.reset(None);
+                            }
+
+                            if let Some(coalescer) = &mut coalescer {
+                                pending.extend(conn.send_batches(coalescer.flush()));
+                            }
+
+                            pending.extend(conn.flush(&mut offline));
+                            pending.extend(conn.send_receipts(&mut receipts));
+                            state.send_replace(ConnectionState::ShuttingDown);
+
+                            shutdown = Some(Shutdown {
+                                frame,
+                                deadline: Box::pin(sleep(timeout)),
+                                expired: false,
+                                tx,
+                            });
+                        }
+
+                        ConnectionControl::OutboundRequest(mut request) => {
+                            pending.extend(router.track(&mut request));
+
+                            match (&mut coalescer, &mut offline) {
+                                (Some(coalescer), _) if coalescer.accepts(&request) => {
+                                    pending.extend(conn.send_batches(coalescer.push(request)));
+                                }
+
+                                // Keep the order of the publish requests by queueing them behind the
+                                // ones that haven't been flushed yet.
+                                (_, Some(queue))
+                                    if queue.accepts(&request)
+                                        && (!conn.is_connected() || !queue.is_empty()) =>
+                                {
+                                    queue.push(request);
+                                    pending.extend(conn.flush(&mut offline));
+                                }
+
+                                _ => pending.extend(conn.request(request)),
+                            }
+                        }
+
+                        ConnectionControl::Receipt(receipt) => match &mut receipts {
+                            Some(queue) => {
+                                if queue.push(receipt) {
+                                    pending.extend(conn.send_receipts(&mut receipts));
+                                }
+                            }
+
+                            None => pending.extend(conn.receive(receipt)),
+                        },
+
+                        ConnectionControl::Route { topic, tx } => {
+                            router.add(topic, tx);
                         }
                     }
 
                     // Control TX has been dropped, shutting down.
                     None => {
-                        conn.disconnect().await.ok();
-                        handler.disconnected(None);
+                        conn.disconnect(None).await.ok();
+                        state.send_replace(ConnectionState::Disconnected);
+                        dispatcher.send(HandlerEvent::Disconnected(None)).await;
                         break;
                     }
                 }
             }
 
+            _ = conn.capacity(), if !conn.has_capacity() => {
+                pending.extend(conn.flush(&mut offline));
+            }
+
+            _ = shutdown_timer(&mut shutdown) => {}
+
+            // Wake up once the outstanding requests complete.
+            _ = conn.idle(), if shutdown.is_some() && !conn.is_drained() => {}
+
+            _ = offline_timer(&offline) => {
+                if let Some(offline) = &mut offline {
+                    offline.purge();
+                }
+            }
+
+            _ = coalesce_timer(&mut coalescer) => {
+                if let Some(coalescer) = &mut coalescer {
+                    pending.extend(conn.send_batches(coalescer.flush()));
+                }
+            }
+
+            event = receipt_event(&mut receipts) => match event {
+                ReceiptEvent::Flush => pending.extend(conn.send_receipts(&mut receipts)),
+
+                ReceiptEvent::Delivery((batch, attempt, result)) => {
+                    let dropped = receipts
+                        .as_mut()
+                        .and_then(|receipts| receipts.complete(batch, attempt, result));
+
+                    if let Some(err) = dropped {
+                        dispatcher.send(HandlerEvent::OutboundError(err)).await;
+                    }
+                }
+            },
+
+            _ = reconnect_timer(&mut reconnect) => {
+                let Some(reconnect) = &mut reconnect else {
+                    continue;
+                };
+
+                let Some(opts) = &reconnect.opts else {
+                    continue;
+                };
+
+                // Built for each attempt, so that a fresh auth token is used.
+                let result = match opts.as_ws_request() {
+                    Ok(request) => conn.connect(request).await,
+                    Err(err) => Err(err.into()),
+                };
+
+                match result {
+                    Ok(()) => {
+                        reconnect.attempt = 0;
+                        state.send_replace(ConnectionState::Connected(chrono::Utc::now()));
+                        dispatcher.send(HandlerEvent::Connected).await;
+                        pending.extend(conn.resubscribe());
+                        pending.extend(conn.flush(&mut offline));
+                        pending.extend(conn.send_receipts(&mut receipts));
+                    }
+
+                    Err(error) => {
+                        dispatcher
+                            .send(HandlerEvent::ReconnectFailed {
+                                attempt: reconnect.attempt,
+                                error,
+                            })
+                            .await;
+
+                        if !reconnect.schedule(&dispatcher, &state).await {
+                            state.send_replace(ConnectionState::Closed(close_reason.clone()));
+                        }
+                    }
+                }
+            }
+
+            Some(result) = pending.next() => {
+                if let Err(err) = result {
+                    dispatcher.send(HandlerEvent::OutboundError(err)).await;
+                }
+            }
+
+            Some(err) = store_error(&mut store_errors) => {
+                dispatcher.send(HandlerEvent::OutboundError(err)).await;
+            }
+
+            // The last subscription stream for the topic has been dropped.
+            Some(topic) = router.next_closed() => {
+                pending.extend(conn.unsubscribe(topic));
+            }
+
             event = conn.select_next_some() => {
                 match event {
                     StreamEvent::InboundSubscriptionRequest(request) => {
-                        handler.message_received(PublishedMessage::from_request(&request));
-                        request.respond(Ok(true)).ok();
+                        metrics::inbound_message(request.data().data.tag);
+                        trace::inbound_message(request.id(), &request.data().data);
+
+                        // Acknowledge the duplicates, so that the Relay doesn't redeliver them.
+                        if dedup
+                            .as_ref()
+                            .is_some_and(|dedup| dedup.is_duplicate(request.id(), &request.data().data))
+                        {
+                            request.respond(Ok(true)).ok();
+                            continue;
+                        }
+
+                        let request = match router.route(request) {
+                            Ok(Some(request)) => request,
+                            Ok(None) => continue,
+
+                            Err(err) => {
+                                dispatcher.send(HandlerEvent::InboundError(err)).await;
+                                continue;
+                            }
+                        };
+
+                        let event = match &config.manual_ack {
+                            Some(ack) => {
+                                let message = PublishedMessage::from_request(&request);
+                                HandlerEvent::MessageWithAck(message, AckHandle::new(request, ack))
+                            }
+
+                            None => HandlerEvent::Message(request),
+                        };
+
+                        dispatcher.send(event).await;
                     }
 
                     StreamEvent::InboundError(error) => {
-                        handler.inbound_error(error);
+                        dispatcher.send(HandlerEvent::InboundError(error)).await;
                     }
 
                     StreamEvent::OutboundError(error) => {
-                        handler.outbound_error(error);
+                        dispatcher.send(HandlerEvent::OutboundError(error)).await;
                     }
 
                     StreamEvent::ConnectionClosed(frame) => {
-                        handler.disconnected(frame);
                         conn.reset();
+                        close_reason = CloseReason(frame.clone());
+                        state.send_replace(ConnectionState::Closed(close_reason.clone()));
+                        dispatcher.send(HandlerEvent::Disconnected(frame)).await;
+
+                        if let Some(reconnect) = &mut reconnect {
+                            reconnect.schedule(&dispatcher, &state).await;
+                        }
                     }
                 }
             }
         }
     }
+
+    // Forward the responses the requests have failed with once disconnected,
+    // instead of dropping them along with the futures awaiting them.
+    while let Some(Some(_)) = pending.next().now_or_never() {}
 }
 
 struct Connection {
     stream: Option<ClientStream>,
+
+    /// Topics re-subscribed on reconnects. Shared with the futures awaiting the
+    /// responses to the subscribe requests.
+    subscriptions: Arc<Mutex<HashSet<Topic>>>,
+    heartbeat: Option<HeartbeatConfig>,
+    outbound_capacity: usize,
+    rtt: RoundTripTime,
+    transport: Arc<dyn Transport>,
+    store: Option<StoreWriter>,
+
+    /// Whether the stored subscriptions have been loaded. Only loaded once per
+    /// [`Client::connect()`], since the tracked topics include them afterwards.
+    ///
+    /// [`Client::connect()`]: super::Client::connect
+    restored: bool,
 }
 
 impl Connection {
-    fn new() -> Self {
-        Self { stream: None }
+    fn new(
+        heartbeat: Option<HeartbeatConfig>,
+        outbound_capacity: usize,
+        rtt: RoundTripTime,
+        transport: Arc<dyn Transport>,
+        store: Option<StoreWriter>,
+    ) -> Self {
+        Self {
+            stream: None,
+            subscriptions: Default::default(),
+            heartbeat,
+            outbound_capacity,
+            rtt,
+            transport,
+            store,
+            restored: false,
+        }
     }
 
     async fn connect(&mut self, request: HttpRequest<()>) -> Result<(), ClientError> {
@@ -112,16 +614,23 @@ impl Connection {
             stream.close(None).await?;
         }
 
-        self.stream = Some(create_stream(request).await?);
+        let stream = create_stream_with_transport(self.transport.as_ref(), request)
+            .await?
+            .with_outbound_capacity(self.outbound_capacity);
+
+        self.stream = Some(match &self.heartbeat {
+            Some(heartbeat) => stream.with_shared_heartbeat(heartbeat.clone(), self.rtt.clone()),
+            None => stream,
+        });
 
         Ok(())
     }
 
-    async fn disconnect(&mut self) -> Result<(), ClientError> {
+    async fn disconnect(&mut self, frame: Option<CloseFrame>) -> Result<(), ClientError> {
         let stream = self.stream.take();
 
         match stream {
-            Some(mut stream) => stream.close(None).await,
+            Some(mut stream) => stream.close(frame).await,
 
             None => Err(WebsocketClientError::ClosingFailed(Box::new(
                 RawTransportError::AlreadyClosed,
@@ -130,17 +639,237 @@ impl Connection {
         }
     }
 
-    fn request(&mut self, request: OutboundRequest) {
-        match &mut self.stream {
-            Some(stream) => stream.send_raw(request),
+    /// Sends the request on the current stream. Returns the future recording
+    /// the subscriptions, if any.
+    ///
+    /// The unsubscribed topics stop being tracked even if disconnected, so that
+    /// they're not re-subscribed on the next connect.
+    fn request(&mut self, mut request: OutboundRequest) -> Option<RecordFuture> {
+        let record = record_subscriptions(&self.subscriptions, self.store.as_ref(), &mut request);
+
+        let Some(stream) = &mut self.stream else {
+            request
+                .tx
+                .send(Err(WebsocketClientError::NotConnected.into()))
+                .ok();
+
+            return record;
+        };
+
+        stream.send_raw(request);
+
+        record
+    }
+
+    /// Sends the coalesced requests, returning the futures distributing the
+    /// responses to the original requests.
+    fn send_batches(
+        &mut self,
+        batches: impl IntoIterator<Item = Batch>,
+    ) -> Vec<BoxFuture<'static, Result<(), ClientError>>> {
+        batches
+            .into_iter()
+            .flat_map(|(request, distribute)| {
+                let record = self.request(request);
+                std::iter::once(distribute).chain(record)
+            })
+            .collect()
+    }
+
+    /// Sends the queued offline requests, as long as there is outbound
+    /// capacity.
+    fn flush(
+        &mut self,
+        offline: &mut Option<OfflineQueue>,
+    ) -> Vec<BoxFuture<'static, Result<(), ClientError>>> {
+        let mut pending = Vec::new();
+
+        let Some(offline) = offline else {
+            return pending;
+        };
+
+        offline.purge();
+
+        while self.is_connected() && self.has_capacity() {
+            let Some(request) = offline.pop() else {
+                break;
+            };
+
+            pending.extend(self.request(request));
+        }
+
+        pending
+    }
+
+    /// Sends the queued receipts, unless disconnected. The responses are
+    /// tracked by the [`ReceiptAggregator`].
+    fn send_receipts(
+        &mut self,
+        receipts: &mut Option<ReceiptAggregator>,
+    ) -> Vec<BoxFuture<'static, Result<(), ClientError>>> {
+        let Some(receipts) = receipts else {
+            return Vec::new();
+        };
+
+        if !self.is_connected() {
+            return Vec::new();
+        }
+
+        receipts
+            .flush()
+            .into_iter()
+            .filter_map(|request| self.request(request))
+            .collect()
+    }
+
+    /// Sends the receipt right away, returning the future resolving with the
+    /// result.
+    fn receive(&mut self, receipt: Receipt) -> Vec<BoxFuture<'static, Result<(), ClientError>>> {
+        let (request, response) = create_request(BatchReceiveMessages {
+            receipts: vec![receipt],
+        });
+
+        let record = self.request(request);
+        let response = Box::pin(async move { into_client_result(response.await) }) as BoxFuture<_>;
+
+        std::iter::once(response).chain(record).collect()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Returns `true` if none of the requests sent on the current stream is
+    /// awaiting the response.
+    fn is_drained(&self) -> bool {
+        self.stream
+            .as_ref()
+            .is_none_or(|stream| stream.pending_requests() == 0)
+    }
+
+    /// Returns a future that resolves once none of the requests sent on the
+    /// current stream is awaiting the response.
+    fn idle(&self) -> impl Future<Output = ()> + 'static {
+        let idle = self.stream.as_ref().map(ClientStream::idle);
+
+        async move {
+            match idle {
+                Some(idle) => idle.await,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    fn has_capacity(&self) -> bool {
+        self.stream
+            .as_ref()
+            .is_none_or(|stream| stream.outbound_capacity() > 0)
+    }
+
+    /// Returns a future that resolves once the outbound queue has capacity.
+    fn capacity(&self) -> impl Future<Output = ()> + 'static {
+        let waiter = self.stream.as_ref().map(ClientStream::outbound_ready);
+
+        async move {
+            match waiter {
+                Some(waiter) => waiter.await,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    /// Re-subscribes all of the tracked topics on the current stream. Returns
+    /// the futures resolving with the result of each batch subscription.
+    ///
+    /// The topics of a batch rejected by the relay stop being tracked, and are
+    /// removed from the store, so that they're not replayed on every reconnect.
+    fn resubscribe(&mut self) -> Vec<BoxFuture<'static, Result<(), ClientError>>> {
+        if self.stream.is_none() {
+            return Vec::new();
+        }
+
+        let topics = crate::lock(&self.subscriptions)
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        topics
+            .chunks(MAX_SUBSCRIPTION_BATCH_SIZE)
+            .flat_map(|chunk| {
+                let topics = chunk.to_vec();
+                let subscriptions = self.subscriptions.clone();
+                let store = self.store.clone();
+
+                let (request, response) = create_request(BatchSubscribe {
+                    topics: topics.clone(),
+                });
+
+                let record = self.request(request);
+                let response = Box::pin(async move {
+                    let result = into_client_result(response.await);
 
-            None => {
-                request
-                    .tx
-                    .send(Err(WebsocketClientError::NotConnected.into()))
-                    .ok();
+                    if let Err(ClientError::Rpc { .. }) = &result {
+                        let mut subscriptions = crate::lock(&subscriptions);
+
+                        for topic in &topics {
+                            subscriptions.remove(topic);
+                        }
+
+                        if let Some(store) = &store {
+                            store.remove(&topics);
+                        }
+                    }
+
+                    result
+                }) as BoxFuture<_>;
+
+                std::iter::once(response).chain(record)
+            })
+            .collect()
+    }
+
+    /// Adds the topics from the subscription store to the tracked ones unless
+    /// they've already been loaded, and re-subscribes all of them on the
+    /// current stream.
+    ///
+    /// The store isn't loaded again on reconnects, since the tracked topics
+    /// already include the stored ones.
+    async fn restore(&mut self) -> Vec<BoxFuture<'static, Result<(), ClientError>>> {
+        if let Some(store) = self.store.as_ref().filter(|_| !self.restored) {
+            match store.load().await {
+                Ok(stored) => {
+                    crate::lock(&self.subscriptions).extend(stored.into_keys());
+                    self.restored = true;
+                }
+
+                Err(err) => {
+                    let err = ClientError::SubscriptionStore(err);
+                    return vec![Box::pin(std::future::ready(Err(err)))];
+                }
             }
         }
+
+        self.resubscribe()
+    }
+
+    /// Stops tracking the topic and unsubscribes from it on the current stream.
+    /// Returns the futures resolving with the result of the unsubscription.
+    fn unsubscribe(&mut self, topic: Topic) -> Vec<BoxFuture<'static, Result<(), ClientError>>> {
+        if self.stream.is_none() {
+            crate::lock(&self.subscriptions).remove(&topic);
+
+            if let Some(store) = &self.store {
+                store.remove(&[topic]);
+            }
+
+            return Vec::new();
+        }
+
+        let (request, response) = create_request(Unsubscribe { topic });
+        let record = self.request(request);
+        let response = Box::pin(async move { into_client_result(response.await) }) as BoxFuture<_>;
+
+        std::iter::once(response).chain(record).collect()
     }
 
     fn reset(&mut self) {
@@ -148,6 +877,121 @@ impl Connection {
     }
 }
 
+fn into_client_result<T, E>(result: Result<T, Error<E>>) -> Result<(), ClientError>
+where
+    E: ServiceError,
+{
+    match result {
+        Ok(_) => Ok(()),
+        Err(Error::Client(err)) => Err(err),
+        Err(Error::Response(err)) => Err(ErrorData::from(err).into()),
+    }
+}
+
+/// Future recording the subscription changes.
+type RecordFuture = BoxFuture<'static, Result<(), ClientError>>;
+
+/// Updates the tracked topics and the subscription store, if any, based on the
+/// outbound request. Unsubscribed topics are removed right away, while the
+/// subscriptions are recorded once the relay responds with their IDs. Returns
+/// the future forwarding the response to the original request and recording
+/// the subscriptions.
+fn record_subscriptions(
+    subscriptions: &Arc<Mutex<HashSet<Topic>>>,
+    store: Option<&StoreWriter>,
+    request: &mut OutboundRequest,
+) -> Option<RecordFuture> {
+    type ParseIds = fn(&serde_json::Value) -> serde_json::Result<Vec<Option<SubscriptionId>>>;
+
+    let unsubscribe = |topics: &[Topic]| {
+        let mut subscriptions = crate::lock(subscriptions);
+
+        for topic in topics {
+            subscriptions.remove(topic);
+        }
+
+        if let Some(store) = store {
+            store.remove(topics);
+        }
+    };
+
+    let (topics, parse): (Vec<Topic>, ParseIds) = match &request.params {
+        Params::Subscribe(data) => (vec![data.topic.clone()], |value| {
+            Ok(vec![Some(SubscriptionId::deserialize(value)?)])
+        }),
+
+        Params::SubscribeBlocking(data) => (vec![data.topic.clone()], |value| {
+            Ok(vec![Some(SubscriptionId::deserialize(value)?)])
+        }),
+
+        Params::BatchSubscribe(data) => (data.topics.clone(), |value| {
+            Ok(Vec::<SubscriptionId>::deserialize(value)?
+                .into_iter()
+                .map(Some)
+                .collect())
+        }),
+
+        Params::BatchSubscribeBlocking(data) => (data.topics.clone(), |value| {
+            Ok(Vec::<SubscriptionResult>::deserialize(value)?
+                .into_iter()
+                .map(|result| match result {
+                    SubscriptionResult::Id(id) => Some(id),
+                    SubscriptionResult::Error(_) => None,
+                })
+                .collect())
+        }),
+
+        Params::Unsubscribe(data) => {
+            unsubscribe(std::slice::from_ref(&data.topic));
+            return None;
+        }
+
+        Params::BatchUnsubscribe(data) => {
+            let topics = data
+                .subscriptions
+                .iter()
+                .map(|data| data.topic.clone())
+                .collect::<Vec<_>>();
+
+            unsubscribe(&topics);
+            return None;
+        }
+
+        _ => return None,
+    };
+
+    let subscriptions = subscriptions.clone();
+    let store = store.cloned();
+    let (tx, rx) = oneshot::channel();
+    let original_tx = std::mem::replace(&mut request.tx, tx);
+
+    Some(Box::pin(async move {
+        let response = rx.await.unwrap_or(Err(ClientError::ChannelClosed));
+
+        // Record the subscriptions before forwarding the response, so that they're
+        // written after the removals of the unsubscriptions made earlier. Failed
+        // requests, and the topics the relay failed to subscribe to, are not
+        // recorded.
+        if let Some(Ok(ids)) = response.as_ref().ok().map(parse) {
+            let recorded = topics
+                .into_iter()
+                .zip(ids)
+                .filter_map(|(topic, id)| Some((topic, id?)))
+                .collect::<Vec<_>>();
+
+            crate::lock(&subscriptions).extend(recorded.iter().map(|(topic, _)| topic.clone()));
+
+            if let Some(store) = store {
+                store.insert(recorded);
+            }
+        }
+
+        original_tx.send(response).ok();
+
+        Ok(())
+    }))
+}
+
 impl Stream for Connection {
     type Item = StreamEvent;
 
@@ -0,0 +1,256 @@
+use {
+    relay_rpc::{
+        domain::MessageId,
+        rpc::{msg_id::get_message_id, SubscriptionData},
+    },
+    std::{
+        collections::{HashMap, VecDeque},
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    tokio::time::Instant,
+};
+
+/// Default number of the recently seen messages remembered by the
+/// de-duplication filter.
+pub const DEFAULT_DEDUP_CAPACITY: usize = 4096;
+
+/// Key identifying the duplicate messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DedupKey {
+    /// SHA-256 hash of the message content (see [`get_message_id()`]). Catches
+    /// the duplicates delivered through both `irn_subscription` and the
+    /// [`FetchMessageStream`][super::FetchMessageStream].
+    #[default]
+    MessageHash,
+
+    /// JSON-RPC ID of the `irn_subscription` request. Only catches the messages
+    /// redelivered by the Relay, e.g. after a reconnect. The fetched messages
+    /// don't have the ID and are always keyed by the hash.
+    MessageId,
+}
+
+/// Inbound message de-duplication configuration.
+///
+/// The filter remembers the recently seen messages within a bounded window,
+/// evicting the least recently seen ones once the capacity is reached, and the
+/// ones not seen for longer than the TTL. Duplicate subscription messages are
+/// acknowledged and dropped before they reach the [`ConnectionHandler`] or any
+/// of the streams.
+///
+/// [`ConnectionHandler`]: super::ConnectionHandler
+#[derive(Debug, Clone)]
+pub struct DedupConfig {
+    /// The maximum number of remembered messages.
+    pub capacity: usize,
+
+    /// Time after which a message is forgotten. Messages are only evicted due
+    /// to the capacity if `None`.
+    pub ttl: Option<Duration>,
+
+    /// Key identifying the duplicate messages.
+    pub key: DedupKey,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_DEDUP_CAPACITY,
+            ttl: None,
+            key: DedupKey::default(),
+        }
+    }
+}
+
+impl DedupConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: impl Into<Option<Duration>>) -> Self {
+        self.ttl = ttl.into();
+        self
+    }
+
+    pub fn with_key(mut self, key: DedupKey) -> Self {
+        self.key = key;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Hash(String),
+    Id(MessageId),
+}
+
+#[derive(Debug)]
+struct Window {
+    config: DedupConfig,
+
+    /// The sequence number and the time of the last sighting of each key.
+    last_seen: HashMap<Key, (u64, Instant)>,
+
+    /// Sightings in the order they happened. Contains stale entries for the
+    /// keys seen again later, which are skipped during eviction.
+    order: VecDeque<(Key, u64)>,
+    seq: u64,
+}
+
+impl Window {
+    /// Records the key, returning `true` if it has been seen within the window.
+    fn check(&mut self, key: Key) -> bool {
+        let now = Instant::now();
+        self.evict(now);
+
+        self.seq += 1;
+        let duplicate = self
+            .last_seen
+            .insert(key.clone(), (self.seq, now))
+            .is_some();
+        self.order.push_back((key, self.seq));
+
+        // Drop the stale entries if the duplicates keep refreshing the same keys.
+        if self.order.len() > self.config.capacity.saturating_mul(2) {
+            let last_seen = &self.last_seen;
+            self.order
+                .retain(|(key, seq)| last_seen.get(key).is_some_and(|(last, _)| last == seq));
+        }
+
+        self.evict(now);
+
+        duplicate
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while let Some((key, seq)) = self.order.front() {
+            let current = self
+                .last_seen
+                .get(key)
+                .filter(|(last, _)| last == seq)
+                .map(|(_, seen)| *seen);
+
+            if let Some(seen) = current {
+                let expired = self
+                    .config
+                    .ttl
+                    .is_some_and(|ttl| now.duration_since(seen) >= ttl);
+
+                if !expired && self.last_seen.len() <= self.config.capacity {
+                    break;
+                }
+
+                self.last_seen.remove(key);
+            }
+
+            self.order.pop_front();
+        }
+    }
+}
+
+/// Shared de-duplication filter used by the connection and the
+/// [`FetchMessageStream`][super::FetchMessageStream]s.
+#[derive(Debug, Clone)]
+pub(super) struct DedupFilter(Arc<Mutex<Window>>);
+
+impl DedupFilter {
+    pub(super) fn new(config: DedupConfig) -> Self {
+        Self(Arc::new(Mutex::new(Window {
+            config,
+            last_seen: HashMap::new(),
+            order: VecDeque::new(),
+            seq: 0,
+        })))
+    }
+
+    /// Returns `true` if the subscription message has been seen before.
+    pub(super) fn is_duplicate(&self, id: MessageId, data: &SubscriptionData) -> bool {
+        let mut window = crate::lock(&self.0);
+
+        let key = match window.config.key {
+            DedupKey::MessageHash => Key::Hash(get_message_id(&data.message)),
+            DedupKey::MessageId => Key::Id(id),
+        };
+
+        window.check(key)
+    }
+
+    /// Forgets the subscription message, so that its redelivery isn't reported
+    /// as a duplicate.
+    pub(super) fn forget(&self, id: MessageId, message: &str) {
+        let mut window = crate::lock(&self.0);
+
+        let key = match window.config.key {
+            DedupKey::MessageHash => Key::Hash(get_message_id(message)),
+            DedupKey::MessageId => Key::Id(id),
+        };
+
+        window.last_seen.remove(&key);
+    }
+
+    /// Returns `true` if the fetched message has been seen before.
+    pub(super) fn is_fetched_duplicate(&self, data: &SubscriptionData) -> bool {
+        let mut window = crate::lock(&self.0);
+        window.check(Key::Hash(get_message_id(&data.message)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, relay_rpc::domain::Topic};
+
+    fn data(message: &str) -> SubscriptionData {
+        SubscriptionData {
+            topic: Topic::generate(),
+            message: message.into(),
+            attestation: None,
+            published_at: 0,
+            tag: 0,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn window() {
+        let filter = DedupFilter::new(
+            DedupConfig::new()
+                .with_capacity(2)
+                .with_ttl(Duration::from_secs(60)),
+        );
+
+        assert!(!filter.is_duplicate(MessageId::new(1), &data("first")));
+        assert!(filter.is_fetched_duplicate(&data("first")));
+        assert!(!filter.is_duplicate(MessageId::new(2), &data("second")));
+
+        // Evicts the least recently seen message.
+        assert!(!filter.is_duplicate(MessageId::new(3), &data("third")));
+        assert!(!filter.is_duplicate(MessageId::new(4), &data("first")));
+        assert!(filter.is_duplicate(MessageId::new(5), &data("third")));
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(!filter.is_duplicate(MessageId::new(6), &data("third")));
+    }
+
+    #[test]
+    fn message_id_key() {
+        let filter = DedupFilter::new(DedupConfig::new().with_key(DedupKey::MessageId));
+
+        assert!(!filter.is_duplicate(MessageId::new(1), &data("message")));
+        assert!(!filter.is_duplicate(MessageId::new(2), &data("message")));
+        assert!(filter.is_duplicate(MessageId::new(1), &data("message")));
+    }
+
+    #[test]
+    fn forget() {
+        let filter = DedupFilter::new(DedupConfig::new());
+
+        assert!(!filter.is_duplicate(MessageId::new(1), &data("message")));
+        filter.forget(MessageId::new(1), "message");
+        assert!(!filter.is_duplicate(MessageId::new(2), &data("message")));
+        assert!(filter.is_duplicate(MessageId::new(3), &data("message")));
+    }
+}
@@ -0,0 +1,139 @@
+use {
+    super::{
+        events::EventSubscribers,
+        inbound::InboundRequest,
+        AckHandle,
+        ClientEvent,
+        ClientEventStream,
+        CloseFrame,
+        ConnectionHandler,
+        PublishedMessage,
+    },
+    crate::ClientError,
+    relay_rpc::rpc::Subscription,
+    std::{sync::Arc, time::Duration},
+    tokio::sync::mpsc,
+};
+
+/// Events delivered to the [`ConnectionHandler`].
+pub(super) enum HandlerEvent {
+    Connected,
+    Disconnected(Option<CloseFrame>),
+    Reconnecting { attempt: u32, delay: Duration },
+    ReconnectFailed { attempt: u32, error: ClientError },
+    Message(InboundRequest<Subscription>),
+    MessageWithAck(PublishedMessage, AckHandle),
+    InboundError(ClientError),
+    OutboundError(ClientError),
+}
+
+/// Delivers the connection events either to the [`ConnectionHandler`] running
+/// in a separate task, or to the [`ClientEventStream`] consumers.
+///
+/// The events are passed through bounded queues, so that a slow consumer
+/// doesn't block the outbound traffic until the queue is full, at which point
+/// the connection stops reading the inbound messages.
+pub(super) enum Dispatcher {
+    Handler(mpsc::Sender<HandlerEvent>),
+    Stream(EventSubscribers),
+}
+
+impl Dispatcher {
+    pub(super) fn spawn<T>(handler: T, capacity: usize) -> Self
+    where
+        T: ConnectionHandler,
+    {
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+
+        tokio::spawn(handler_event_loop(rx, handler));
+
+        Self::Handler(tx)
+    }
+
+    pub(super) fn stream(capacity: usize) -> (Self, ClientEventStream) {
+        let subscribers = EventSubscribers::new(capacity);
+        let stream = ClientEventStream::new(subscribers.clone());
+
+        (Self::Stream(subscribers), stream)
+    }
+
+    /// Queues the event for the consumers, waiting for the queue capacity if
+    /// it's full.
+    pub(super) async fn send(&self, event: HandlerEvent) {
+        match self {
+            Self::Handler(tx) => {
+                tx.send(event).await.ok();
+            }
+
+            Self::Stream(subscribers) => {
+                let event = match event {
+                    HandlerEvent::Connected => ClientEvent::Connected,
+
+                    HandlerEvent::Disconnected(frame) => ClientEvent::Disconnected(frame),
+
+                    HandlerEvent::Reconnecting { attempt, delay } => {
+                        ClientEvent::Reconnecting { attempt, delay }
+                    }
+
+                    HandlerEvent::ReconnectFailed { attempt, error } => {
+                        ClientEvent::ReconnectFailed {
+                            attempt,
+                            error: Arc::new(error),
+                        }
+                    }
+
+                    HandlerEvent::Message(request) => {
+                        let message = PublishedMessage::from_request(&request);
+                        subscribers.send(ClientEvent::Message(message)).await;
+                        request.respond(Ok(true)).ok();
+                        return;
+                    }
+
+                    HandlerEvent::MessageWithAck(message, ack) => {
+                        ClientEvent::MessageWithAck(message, ack)
+                    }
+
+                    HandlerEvent::InboundError(error) => ClientEvent::InboundError(Arc::new(error)),
+
+                    HandlerEvent::OutboundError(error) => {
+                        ClientEvent::OutboundError(Arc::new(error))
+                    }
+                };
+
+                subscribers.send(event).await;
+            }
+        }
+    }
+}
+
+async fn handler_event_loop<T>(mut rx: mpsc::Receiver<HandlerEvent>, mut handler: T)
+where
+    T: ConnectionHandler,
+{
+    while let Some(event) = rx.recv().await {
+        match event {
+            HandlerEvent::Connected => handler.connected(),
+
+            HandlerEvent::Disconnected(frame) => handler.disconnected(frame),
+
+            HandlerEvent::Reconnecting { attempt, delay } => handler.reconnecting(attempt, delay),
+
+            HandlerEvent::ReconnectFailed { attempt, error } => {
+                handler.reconnect_failed(attempt, error)
+            }
+
+            HandlerEvent::Message(request) => {
+                handler.message_received(PublishedMessage::from_request(&request));
+                request.respond(Ok(true)).ok();
+            }
+
+            HandlerEvent::MessageWithAck(message, ack) => {
+                handler.message_received_with_ack(message, ack)
+            }
+
+            HandlerEvent::InboundError(error) => handler.inbound_error(error),
+
+            HandlerEvent::OutboundError(error) => handler.outbound_error(error),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use {
+    super::{AckHandle, CloseFrame, PublishedMessage},
+    crate::ClientError,
+    futures_util::Stream,
+    std::{
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+        time::Duration,
+    },
+    tokio::sync::mpsc,
+};
+
+/// Connection events produced by the [`ClientEventStream`]. These mirror the
+/// [`ConnectionHandler`][super::ConnectionHandler] callbacks.
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// A connection to the Relay is established.
+    Connected,
+
+    /// The Relay connection is closed.
+    Disconnected(Option<CloseFrame>),
+
+    /// A reconnection attempt is scheduled after the connection was lost.
+    Reconnecting { attempt: u32, delay: Duration },
+
+    /// A reconnection attempt failed.
+    ReconnectFailed {
+        attempt: u32,
+        error: Arc<ClientError>,
+    },
+
+    /// A message is received from the Relay.
+    Message(PublishedMessage),
+
+    /// A message is received from the Relay in the manual acknowledgement mode
+    /// (see [`ClientConfig::manual_ack`]). The message is acknowledged by the
+    /// first consumer calling [`AckHandle::ack()`] or [`AckHandle::nack()`].
+    ///
+    /// [`ClientConfig::manual_ack`]: super::ClientConfig::manual_ack
+    MessageWithAck(PublishedMessage, AckHandle),
+
+    /// An inbound error occurred, such as data deserialization failure, or an
+    /// unknown response message ID.
+    InboundError(Arc<ClientError>),
+
+    /// An outbound error occurred, i.e. failed to write to the websocket
+    /// stream.
+    OutboundError(Arc<ClientError>),
+}
+
+/// The set of [`ClientEventStream`] consumers.
+#[derive(Debug, Clone)]
+pub(super) struct EventSubscribers {
+    senders: Arc<Mutex<Vec<mpsc::Sender<ClientEvent>>>>,
+    capacity: usize,
+}
+
+impl EventSubscribers {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            senders: Default::default(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    fn subscribe(&self) -> mpsc::Receiver<ClientEvent> {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        self.lock().push(tx);
+        rx
+    }
+
+    /// Delivers the event to each of the consumers, waiting for the queue
+    /// capacity of the slowest one.
+    pub(super) async fn send(&self, event: ClientEvent) {
+        let senders = self.lock().clone();
+        let mut closed = false;
+
+        for tx in senders {
+            closed |= tx.send(event.clone()).await.is_err();
+        }
+
+        if closed {
+            self.lock().retain(|tx| !tx.is_closed());
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<mpsc::Sender<ClientEvent>>> {
+        crate::lock(&self.senders)
+    }
+}
+
+/// [`Stream`] of the [`ClientEvent`]s, an alternative to implementing the
+/// [`ConnectionHandler`][super::ConnectionHandler].
+///
+/// Cloning the stream creates an independent consumer, which receives all of
+/// the events produced after it was created. Each consumer has its own bounded
+/// queue, and the connection stops reading the inbound messages while any of
+/// the queues is full.
+#[derive(Debug)]
+pub struct ClientEventStream {
+    rx: mpsc::Receiver<ClientEvent>,
+    subscribers: EventSubscribers,
+}
+
+impl ClientEventStream {
+    pub(super) fn new(subscribers: EventSubscribers) -> Self {
+        Self {
+            rx: subscribers.subscribe(),
+            subscribers,
+        }
+    }
+}
+
+impl Clone for ClientEventStream {
+    fn clone(&self) -> Self {
+        Self::new(self.subscribers.clone())
+    }
+}
+
+impl Stream for ClientEventStream {
+    type Item = ClientEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
@@ -1,5 +1,5 @@
 use {
-    super::{create_request, Client, ResponseFuture},
+    super::{Client, ResponseFuture},
     crate::error::Error,
     futures_util::{FutureExt, Stream},
     relay_rpc::{
@@ -55,6 +55,14 @@ impl Stream for FetchMessageStream {
             if let Some(batch) = &mut self.batch {
                 // Drain the items from the batch, if we have one.
                 match batch.next() {
+                    // Skip the messages already received by the client.
+                    Some(data)
+                        if self
+                            .client
+                            .dedup
+                            .as_ref()
+                            .is_some_and(|dedup| dedup.is_fetched_duplicate(&data)) => {}
+
                     Some(data) => {
                         return Poll::Ready(Some(Ok(data)));
                     }
@@ -90,10 +98,7 @@ impl Stream for FetchMessageStream {
             } else if self.has_more {
                 // We have neither a batch, or a batch future, but `has_more` flag is set. Set
                 // up a future to receive the next batch.
-                let (request, batch_fut) = create_request(self.request.clone());
-
-                self.client.request(request);
-                self.batch_fut = Some(batch_fut);
+                self.batch_fut = Some(self.client.send(self.request.clone()));
             } else {
                 // The stream can't produce any more items, since it doesn't have neither a
                 // batch of data or a future for receiving the next batch, and `has_more` flag
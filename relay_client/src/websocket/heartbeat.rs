@@ -0,0 +1,137 @@
+use {
+    super::HeartbeatConfig,
+    std::{
+        future::Future,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+        time::Duration,
+    },
+    tokio::time::{interval_at, sleep, Instant, Interval, MissedTickBehavior, Sleep},
+    tokio_tungstenite::tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Bytes,
+    },
+};
+
+/// The most recently measured websocket round-trip time. Shared between the
+/// [`ClientStream`][super::ClientStream] and the
+/// [`Client`][super::Client] handles.
+#[derive(Debug, Clone, Default)]
+pub(super) struct RoundTripTime(Arc<AtomicU64>);
+
+impl RoundTripTime {
+    pub(super) fn get(&self) -> Option<Duration> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    fn set(&self, rtt: Duration) {
+        // Zero is reserved for the "not measured" state.
+        let micros = u64::try_from(rtt.as_micros()).unwrap_or(u64::MAX).max(1);
+        self.0.store(micros, Ordering::Relaxed);
+    }
+}
+
+/// Action requested by the heartbeat when polled.
+pub(super) enum HeartbeatAction {
+    /// A ping with the provided payload should be sent.
+    Ping(Bytes),
+
+    /// The pong has not been received in time. The connection should be
+    /// considered dead.
+    TimedOut,
+}
+
+/// Websocket ping/pong liveness detection state.
+pub(super) struct Heartbeat {
+    config: HeartbeatConfig,
+    interval: Interval,
+    deadline: Option<Pin<Box<Sleep>>>,
+    pending: Option<(u64, Instant)>,
+    counter: u64,
+    rtt: RoundTripTime,
+}
+
+impl Heartbeat {
+    pub(super) fn new(config: HeartbeatConfig, rtt: RoundTripTime) -> Self {
+        let mut interval = interval_at(Instant::now() + config.interval, config.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Self {
+            config,
+            interval,
+            deadline: None,
+            pending: None,
+            counter: 0,
+            rtt,
+        }
+    }
+
+    pub(super) fn poll(&mut self, cx: &mut Context<'_>) -> Poll<HeartbeatAction> {
+        if let Some(deadline) = &mut self.deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                self.deadline = None;
+                return Poll::Ready(HeartbeatAction::TimedOut);
+            }
+        }
+
+        let mut action = Poll::Pending;
+
+        // Keep polling the interval until it's pending, so that the waker is
+        // registered for the next tick. Only one ping is in flight at a time.
+        while self.interval.poll_tick(cx).is_ready() {
+            if self.pending.is_some() || action.is_ready() {
+                continue;
+            }
+
+            self.counter = self.counter.wrapping_add(1);
+            self.pending = Some((self.counter, Instant::now()));
+
+            let mut deadline = Box::pin(sleep(self.config.timeout));
+            let _ = deadline.as_mut().poll(cx);
+            self.deadline = Some(deadline);
+
+            action = Poll::Ready(HeartbeatAction::Ping(Bytes::copy_from_slice(
+                &self.counter.to_be_bytes(),
+            )));
+        }
+
+        action
+    }
+
+    /// Processes the pong payload, updating the round-trip time if it matches
+    /// the ping in flight.
+    pub(super) fn pong_received(&mut self, payload: &[u8]) {
+        let Some((counter, sent_at)) = self.pending else {
+            return;
+        };
+
+        if payload == counter.to_be_bytes() {
+            self.rtt.set(sent_at.elapsed());
+            self.pending = None;
+            self.deadline = None;
+        }
+    }
+
+    pub(super) fn round_trip_time(&self) -> Option<Duration> {
+        self.rtt.get()
+    }
+
+    /// The close frame reported when the pong has not been received in time.
+    pub(super) fn timeout_frame(&self) -> CloseFrame {
+        CloseFrame {
+            code: CloseCode::Abnormal,
+            reason: format!(
+                "Heartbeat timeout: no pong received within {:?}",
+                self.config.timeout
+            )
+            .into(),
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use {
+    super::outbound::OutboundRequest,
+    crate::ClientError,
+    relay_rpc::rpc::Params,
+    std::{collections::VecDeque, time::Duration},
+    tokio::time::{sleep_until, Instant},
+};
+
+/// Default maximum number of the publish requests buffered while the client is
+/// disconnected.
+pub const DEFAULT_OFFLINE_QUEUE_SIZE: usize = 256;
+
+/// Behavior of the [`OfflineQueueConfig`] when the queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued request to make room for the new one.
+    #[default]
+    DropOldest,
+
+    /// Reject the new request, keeping the queued ones.
+    RejectNew,
+}
+
+/// Buffering of the `irn_publish` requests made while the client is
+/// disconnected.
+///
+/// Instead of failing with [`WebsocketClientError::NotConnected`], the publish
+/// requests are queued and sent in order once the connection is
+/// (re)established. The requests that don't fit the queue fail with
+/// [`ClientError::QueueFull`] according to the [`OverflowPolicy`], and the
+/// requests that stay in the queue for longer than their `ttl_secs` fail with
+/// [`ClientError::MessageExpired`].
+///
+/// Note that the [`ClientConfig::request_timeout`] still applies to the queued
+/// requests.
+///
+/// [`WebsocketClientError::NotConnected`]: super::WebsocketClientError::NotConnected
+/// [`ClientConfig::request_timeout`]: super::ClientConfig::request_timeout
+#[derive(Debug, Clone)]
+pub struct OfflineQueueConfig {
+    /// The maximum number of queued requests.
+    pub max_messages: usize,
+
+    /// The maximum total size of the queued messages, in bytes. Unlimited if
+    /// `None`.
+    pub max_bytes: Option<usize>,
+
+    /// Behavior when either of the limits is exceeded.
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for OfflineQueueConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: DEFAULT_OFFLINE_QUEUE_SIZE,
+            max_bytes: None,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+impl OfflineQueueConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = max_messages;
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: impl Into<Option<usize>>) -> Self {
+        self.max_bytes = max_bytes.into();
+        self
+    }
+
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+struct QueuedRequest {
+    request: OutboundRequest,
+    size: usize,
+    expires_at: Instant,
+}
+
+/// Queue of the publish requests waiting for the connection.
+pub(super) struct OfflineQueue {
+    config: OfflineQueueConfig,
+    queue: VecDeque<QueuedRequest>,
+    bytes: usize,
+}
+
+impl OfflineQueue {
+    pub(super) fn new(config: OfflineQueueConfig) -> Self {
+        Self {
+            config,
+            queue: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Returns `true` if the request can be queued.
+    pub(super) fn accepts(&self, request: &OutboundRequest) -> bool {
+        matches!(request.params, Params::Publish(_))
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Queues the request, applying the overflow policy if the queue is full.
+    pub(super) fn push(&mut self, request: OutboundRequest) {
+        let Params::Publish(data) = &request.params else {
+            request.tx.send(Err(ClientError::InvalidRequestType)).ok();
+            return;
+        };
+
+        let size = data.message.len();
+        let expires_at = Instant::now() + Duration::from_secs(data.ttl_secs.into());
+
+        self.purge();
+
+        let fits = |queue: &Self| {
+            queue.queue.len() < queue.config.max_messages
+                && queue
+                    .config
+                    .max_bytes
+                    .is_none_or(|max| queue.bytes + size <= max)
+        };
+
+        while !fits(self) {
+            match self.config.overflow {
+                OverflowPolicy::DropOldest if !self.queue.is_empty() => {
+                    if let Some(dropped) = self.pop() {
+                        dropped.tx.send(Err(ClientError::QueueFull)).ok();
+                    }
+                }
+
+                _ => {
+                    request.tx.send(Err(ClientError::QueueFull)).ok();
+                    return;
+                }
+            }
+        }
+
+        self.bytes += size;
+        self.queue.push_back(QueuedRequest {
+            request,
+            size,
+            expires_at,
+        });
+    }
+
+    /// Takes the oldest queued request.
+    pub(super) fn pop(&mut self) -> Option<OutboundRequest> {
+        let queued = self.queue.pop_front()?;
+        self.bytes -= queued.size;
+        Some(queued.request)
+    }
+
+    /// Fails all of the queued requests with the error.
+    pub(super) fn clear(&mut self, err: impl Fn() -> ClientError) {
+        while let Some(request) = self.pop() {
+            request.tx.send(Err(err())).ok();
+        }
+    }
+
+    /// Waits for the earliest of the queued requests to expire. Pending if the
+    /// queue is empty.
+    pub(super) async fn wait_expired(&self) {
+        match self.queue.iter().map(|queued| queued.expires_at).min() {
+            Some(deadline) => sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Fails the expired requests, and discards the requests that are no
+    /// longer awaited.
+    pub(super) fn purge(&mut self) {
+        let now = Instant::now();
+
+        let (expired, retained): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut self.queue)
+            .into_iter()
+            .partition(|queued| queued.expires_at <= now || queued.request.tx.is_closed());
+
+        self.queue = retained;
+
+        for queued in expired {
+            self.bytes -= queued.size;
+            queued
+                .request
+                .tx
+                .send(Err(ClientError::MessageExpired))
+                .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{super::outbound::create_request, *},
+        crate::{error::Error, websocket::ResponseFuture},
+        relay_rpc::{domain::Topic, rpc::Publish},
+    };
+
+    fn publish(message: &str, ttl_secs: u32) -> (OutboundRequest, ResponseFuture<Publish>) {
+        create_request(Publish {
+            topic: Topic::generate(),
+            message: message.into(),
+            attestation: None,
+            ttl_secs,
+            tag: 0,
+            prompt: false,
+            analytics: None,
+        })
+    }
+
+    fn queued_messages(queue: &mut OfflineQueue) -> Vec<String> {
+        std::iter::from_fn(|| queue.pop())
+            .filter_map(|request| Some(request.params.into_publish().ok()?.message.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn overflow() {
+        let mut queue = OfflineQueue::new(OfflineQueueConfig::new().with_max_messages(2));
+        let (first, first_response) = publish("first", 300);
+        let (second, _second_response) = publish("second", 300);
+        let (third, _third_response) = publish("third", 300);
+
+        queue.push(first);
+        queue.push(second);
+        queue.push(third);
+
+        assert!(matches!(
+            first_response.await,
+            Err(Error::Client(ClientError::QueueFull))
+        ));
+        assert_eq!(queued_messages(&mut queue), ["second", "third"]);
+
+        let mut queue = OfflineQueue::new(
+            OfflineQueueConfig::new()
+                .with_max_bytes(10)
+                .with_overflow(OverflowPolicy::RejectNew),
+        );
+        let (first, _first_response) = publish("first", 300);
+        let (second, second_response) = publish("second", 300);
+
+        queue.push(first);
+        queue.push(second);
+
+        assert!(matches!(
+            second_response.await,
+            Err(Error::Client(ClientError::QueueFull))
+        ));
+        assert_eq!(queued_messages(&mut queue), ["first"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expiry() {
+        let mut queue = OfflineQueue::new(OfflineQueueConfig::new());
+        let (short, short_response) = publish("short", 60);
+        let (long, _long_response) = publish("long", 300);
+
+        queue.push(short);
+        queue.push(long);
+
+        queue.wait_expired().await;
+        queue.purge();
+
+        assert!(matches!(
+            short_response.await,
+            Err(Error::Client(ClientError::MessageExpired))
+        ));
+        assert_eq!(queued_messages(&mut queue), ["long"]);
+    }
+}
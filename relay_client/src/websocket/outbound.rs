@@ -7,8 +7,12 @@ use {
         marker::PhantomData,
         pin::Pin,
         task::{ready, Context, Poll},
+        time::Duration,
+    },
+    tokio::{
+        sync::oneshot,
+        time::{sleep, Sleep},
     },
-    tokio::sync::oneshot,
 };
 
 /// An outbound request wrapper created by [`create_request()`]. Intended be
@@ -29,11 +33,15 @@ impl OutboundRequest {
 }
 
 /// Future that resolves with the RPC response for the specified request.
+///
+/// Dropping the future discards the pending request, so that the response is
+/// no longer tracked by the connection.
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 #[pin_project]
 pub struct ResponseFuture<T> {
     #[pin]
     rx: oneshot::Receiver<Result<serde_json::Value, ClientError>>,
+    deadline: Option<Pin<Box<Sleep>>>,
     _marker: PhantomData<T>,
 }
 
@@ -41,9 +49,20 @@ impl<T> ResponseFuture<T> {
     pub(super) fn new(rx: oneshot::Receiver<Result<serde_json::Value, ClientError>>) -> Self {
         Self {
             rx,
+            deadline: None,
             _marker: PhantomData,
         }
     }
+
+    /// Sets the response timeout, counting from the moment this is called, and
+    /// replacing any previously set timeout.
+    ///
+    /// If the response doesn't arrive in time, the future resolves with
+    /// [`ClientError::RequestTimeout`] and the pending request is discarded.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Box::pin(sleep(timeout)));
+        self
+    }
 }
 
 impl<T> Future for ResponseFuture<T>
@@ -53,9 +72,24 @@ where
     type Output = Result<T::Response, Error<T::Error>>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
+        let mut this = self.project();
+
+        let result = match this.rx.as_mut().poll(cx) {
+            Poll::Ready(result) => result.map_err(|_| ClientError::ChannelClosed)?,
+
+            Poll::Pending => {
+                if let Some(deadline) = this.deadline {
+                    ready!(deadline.as_mut().poll(cx));
+
+                    // Let the connection know that the response is no longer expected.
+                    this.rx.close();
 
-        let result = ready!(this.rx.poll(cx)).map_err(|_| ClientError::ChannelClosed)?;
+                    return Poll::Ready(Err(ClientError::RequestTimeout.into()));
+                }
+
+                return Poll::Pending;
+            }
+        };
 
         let result = match result {
             Ok(value) => serde_json::from_value(value).map_err(ClientError::Deserialization),
@@ -80,6 +114,13 @@ impl<T> EmptyResponseFuture<T> {
     pub(super) fn new(rx: ResponseFuture<T>) -> Self {
         Self { rx }
     }
+
+    /// Sets the response timeout. See [`ResponseFuture::timeout()`].
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self {
+            rx: self.rx.timeout(timeout),
+        }
+    }
 }
 
 impl<T> Future for EmptyResponseFuture<T>
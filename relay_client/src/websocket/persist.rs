@@ -0,0 +1,214 @@
+use {
+    crate::{
+        error::BoxError,
+        store::{SubscriptionStore, Subscriptions},
+    },
+    relay_rpc::domain::{SubscriptionId, Topic},
+    std::{collections::HashMap, sync::Arc, time::Duration},
+    tokio::{
+        sync::{mpsc, oneshot},
+        time::sleep,
+    },
+};
+
+/// Time the changes are gathered for before they're written to the store, so
+/// that the subscriptions made in quick succession are written at once.
+const WRITE_DELAY: Duration = Duration::from_millis(50);
+
+/// Pending changes of the stored subscriptions. `None` removes the topic.
+type Changes = HashMap<Topic, Option<SubscriptionId>>;
+
+type LoadResult = Result<Subscriptions, BoxError>;
+
+enum Command {
+    Write(Changes),
+
+    /// Loads the subscriptions once the pending changes are written.
+    Load(oneshot::Sender<LoadResult>),
+}
+
+/// Records the subscription changes in the [`SubscriptionStore`] from a
+/// background task, so that a slow store doesn't hold up the connection.
+///
+/// The changes are coalesced by topic and written after [`WRITE_DELAY`], on the
+/// blocking thread pool. The changes made while a write is in progress are
+/// written once it completes. The remaining changes are written once the writer
+/// is dropped.
+#[derive(Clone)]
+pub(super) struct StoreWriter {
+    tx: mpsc::UnboundedSender<Command>,
+}
+
+/// Errors of the store writes performed by the [`StoreWriter`].
+pub(super) type StoreErrors = mpsc::UnboundedReceiver<BoxError>;
+
+impl StoreWriter {
+    pub(super) fn spawn(store: Arc<dyn SubscriptionStore>) -> (Self, StoreErrors) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (errors_tx, errors_rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(write_loop(store, rx, errors_tx));
+
+        (Self { tx }, errors_rx)
+    }
+
+    /// Returns the stored subscriptions. The pending changes are written
+    /// beforehand, so that they're included.
+    pub(super) async fn load(&self) -> LoadResult {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(Command::Load(tx)).ok();
+
+        rx.await
+            .unwrap_or_else(|_| Err("subscription store writer stopped".into()))
+    }
+
+    pub(super) fn insert(&self, subscriptions: Vec<(Topic, SubscriptionId)>) {
+        let changes = subscriptions
+            .into_iter()
+            .map(|(topic, id)| (topic, Some(id)))
+            .collect();
+
+        self.tx.send(Command::Write(changes)).ok();
+    }
+
+    pub(super) fn remove(&self, topics: &[Topic]) {
+        let changes = topics.iter().map(|topic| (topic.clone(), None)).collect();
+        self.tx.send(Command::Write(changes)).ok();
+    }
+}
+
+async fn write_loop(
+    store: Arc<dyn SubscriptionStore>,
+    mut rx: mpsc::UnboundedReceiver<Command>,
+    errors: mpsc::UnboundedSender<BoxError>,
+) {
+    while let Some(command) = rx.recv().await {
+        let mut changes = match command {
+            Command::Write(changes) => changes,
+
+            Command::Load(tx) => {
+                tx.send(load(&store).await).ok();
+                continue;
+            }
+        };
+
+        let mut load_tx = None;
+        let delay = sleep(WRITE_DELAY);
+        tokio::pin!(delay);
+
+        loop {
+            tokio::select! {
+                _ = &mut delay => break,
+
+                next = rx.recv() => match next {
+                    Some(Command::Write(next)) => changes.extend(next),
+
+                    // Write the changes right away instead of holding up the load.
+                    Some(Command::Load(tx)) => {
+                        load_tx = Some(tx);
+                        break;
+                    }
+
+                    None => break,
+                },
+            }
+        }
+
+        let writer = store.clone();
+
+        let result = tokio::task::spawn_blocking(move || write(writer.as_ref(), changes))
+            .await
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(err) = result {
+            errors.send(err).ok();
+        }
+
+        if let Some(tx) = load_tx {
+            tx.send(load(&store).await).ok();
+        }
+    }
+}
+
+async fn load(store: &Arc<dyn SubscriptionStore>) -> LoadResult {
+    let store = store.clone();
+
+    tokio::task::spawn_blocking(move || store.load())
+        .await
+        .unwrap_or_else(|err| Err(err.into()))
+}
+
+fn write(store: &dyn SubscriptionStore, changes: Changes) -> Result<(), BoxError> {
+    let (inserted, removed): (Vec<_>, Vec<_>) =
+        changes.into_iter().partition(|(_, id)| id.is_some());
+
+    if !inserted.is_empty() {
+        let inserted = inserted
+            .into_iter()
+            .filter_map(|(topic, id)| Some((topic, id?)))
+            .collect();
+
+        store.insert(inserted)?;
+    }
+
+    if !removed.is_empty() {
+        let removed = removed
+            .into_iter()
+            .map(|(topic, _)| topic)
+            .collect::<Vec<_>>();
+        store.remove(&removed)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::store::MemorySubscriptionStore,
+        std::sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[derive(Debug, Default)]
+    struct CountingStore {
+        inner: MemorySubscriptionStore,
+        writes: AtomicUsize,
+    }
+
+    impl SubscriptionStore for CountingStore {
+        fn load(&self) -> Result<Subscriptions, BoxError> {
+            self.inner.load()
+        }
+
+        fn insert(&self, subscriptions: Vec<(Topic, SubscriptionId)>) -> Result<(), BoxError> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            self.inner.insert(subscriptions)
+        }
+
+        fn remove(&self, topics: &[Topic]) -> Result<(), BoxError> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            self.inner.remove(topics)
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesced_writes() {
+        let store = Arc::new(CountingStore::default());
+        let (writer, _errors) = StoreWriter::spawn(store.clone());
+
+        let first = Topic::generate();
+        let second = Topic::generate();
+        let id = SubscriptionId::generate();
+
+        writer.insert(vec![(first.clone(), SubscriptionId::generate())]);
+        writer.insert(vec![(second.clone(), id.clone())]);
+        writer.remove(&[first]);
+
+        tokio::time::sleep(WRITE_DELAY * 4).await;
+
+        // Only the latest change of each topic is written, with a single insert.
+        assert_eq!(store.load().unwrap(), [(second, id)].into_iter().collect());
+        assert_eq!(store.writes.load(Ordering::SeqCst), 2);
+    }
+}
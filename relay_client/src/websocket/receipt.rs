@@ -0,0 +1,354 @@
+use {
+    super::outbound::OutboundRequest,
+    crate::{retry::RetryPolicy, ClientError},
+    futures_util::{future::BoxFuture, stream::FuturesUnordered, StreamExt},
+    relay_rpc::rpc::{BatchReceiveMessages, Params, Receipt, MAX_RECEIVE_BATCH_SIZE},
+    std::{collections::VecDeque, pin::Pin, time::Duration},
+    tokio::{
+        sync::oneshot,
+        time::{sleep, sleep_until, Instant, Sleep},
+    },
+};
+
+/// Default time the receipts are gathered for before being sent.
+pub const DEFAULT_RECEIPT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Aggregation of the message receipts into `irn_batchReceive` requests.
+///
+/// The receipts of the messages marked as processed with
+/// [`Client::mark_processed()`] are queued, and sent as
+/// [`BatchReceiveMessages`] requests once `max_batch_size` receipts are queued,
+/// or `flush_interval` after the first of them was queued. The receipts are
+/// held while the client is disconnected, and are sent once the connection is
+/// (re)established.
+///
+/// The batches failing with a retryable error are re-queued and sent again
+/// according to the [`RetryPolicy`]. The receipts that can't be delivered are
+/// dropped, and the error is reported as an outbound error.
+///
+/// [`Client::mark_processed()`]: super::Client::mark_processed
+#[derive(Debug, Clone)]
+pub struct ReceiptConfig {
+    /// The maximum number of receipts per request. Capped at
+    /// [`MAX_RECEIVE_BATCH_SIZE`].
+    pub max_batch_size: usize,
+
+    /// The maximum time the receipts are queued for before being sent.
+    pub flush_interval: Duration,
+
+    /// Retry policy of the failed batches.
+    pub retry: RetryPolicy,
+}
+
+impl Default for ReceiptConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: MAX_RECEIVE_BATCH_SIZE,
+            flush_interval: DEFAULT_RECEIPT_FLUSH_INTERVAL,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl ReceiptConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+}
+
+/// Receipts of a sent batch and its attempt number, along with the result of
+/// the request.
+type Delivery = (Vec<Receipt>, u32, Result<(), ClientError>);
+
+pub(super) enum ReceiptEvent {
+    /// The flush interval or the retry delay has passed.
+    Flush,
+
+    /// The Relay has responded to one of the sent batches.
+    Delivery(Delivery),
+}
+
+/// Queue of the receipts waiting to be sent. See [`ReceiptConfig`].
+pub(super) struct ReceiptAggregator {
+    config: ReceiptConfig,
+    queue: VecDeque<Receipt>,
+
+    /// The failed batches waiting to be retried, along with the number of
+    /// attempts made.
+    retries: VecDeque<(Vec<Receipt>, u32)>,
+    timer: Option<Pin<Box<Sleep>>>,
+    in_flight: FuturesUnordered<BoxFuture<'static, Delivery>>,
+}
+
+impl ReceiptAggregator {
+    pub(super) fn new(config: ReceiptConfig) -> Self {
+        Self {
+            config,
+            queue: VecDeque::new(),
+            retries: VecDeque::new(),
+            timer: None,
+            in_flight: FuturesUnordered::new(),
+        }
+    }
+
+    fn batch_size(&self) -> usize {
+        self.config.max_batch_size.clamp(1, MAX_RECEIVE_BATCH_SIZE)
+    }
+
+    /// Queues the receipt. Returns `true` if a full batch is queued and should
+    /// be sent right away.
+    pub(super) fn push(&mut self, receipt: Receipt) -> bool {
+        self.queue.push_back(receipt);
+
+        if self.timer.is_none() {
+            self.timer = Some(Box::pin(sleep(self.config.flush_interval)));
+        }
+
+        self.queue.len() >= self.batch_size()
+    }
+
+    /// Returns `true` if there are no queued or unconfirmed receipts.
+    pub(super) fn is_empty(&self) -> bool {
+        self.queue.is_empty() && self.retries.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// Drops all of the queued receipts.
+    pub(super) fn clear(&mut self) {
+        self.queue.clear();
+        self.retries.clear();
+        self.timer = None;
+    }
+
+    /// Waits for the flush interval or the retry delay to pass, or for the
+    /// response to one of the sent batches.
+    pub(super) async fn next_event(&mut self) -> ReceiptEvent {
+        let timer = async {
+            match &mut self.timer {
+                Some(timer) => timer.await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let delivery = async {
+            match self.in_flight.next().await {
+                Some(delivery) => delivery,
+                None => std::future::pending().await,
+            }
+        };
+
+        let event = tokio::select! {
+            _ = timer => ReceiptEvent::Flush,
+            delivery = delivery => ReceiptEvent::Delivery(delivery),
+        };
+
+        if let ReceiptEvent::Flush = event {
+            self.timer = None;
+        }
+
+        event
+    }
+
+    /// Takes all of the queued receipts, returning the batch requests to send.
+    /// The failed batches are retried first, each as a separate request.
+    pub(super) fn flush(&mut self) -> Vec<OutboundRequest> {
+        self.timer = None;
+
+        let mut requests = Vec::new();
+
+        while let Some((receipts, attempts)) = self.retries.pop_front() {
+            requests.push(self.send(receipts, attempts + 1));
+        }
+
+        let batch_size = self.batch_size();
+
+        while !self.queue.is_empty() {
+            let count = self.queue.len().min(batch_size);
+            let receipts = self.queue.drain(..count).collect::<Vec<_>>();
+            requests.push(self.send(receipts, 1));
+        }
+
+        requests
+    }
+
+    /// Creates the batch request, tracking its response.
+    fn send(&mut self, receipts: Vec<Receipt>, attempt: u32) -> OutboundRequest {
+        let (tx, rx) = oneshot::channel();
+        let request = OutboundRequest::new(
+            Params::BatchReceiveMessages(BatchReceiveMessages {
+                receipts: receipts.clone(),
+            }),
+            tx,
+        );
+
+        self.in_flight.push(Box::pin(async move {
+            let result = match rx.await {
+                Ok(result) => result.map(drop),
+                Err(_) => Err(ClientError::ChannelClosed),
+            };
+
+            (receipts, attempt, result)
+        }));
+
+        request
+    }
+
+    /// Records the result of the batch sent as the `attempt`, re-queueing the
+    /// batch if it should be retried. Returns the error if the receipts are
+    /// dropped.
+    pub(super) fn complete(
+        &mut self,
+        receipts: Vec<Receipt>,
+        attempt: u32,
+        result: Result<(), ClientError>,
+    ) -> Option<ClientError> {
+        let err = result.err()?;
+
+        let Some(delay) = self.config.retry.retry_delay(attempt, &err) else {
+            return Some(err);
+        };
+
+        self.retries.push_back((receipts, attempt));
+
+        // Don't postpone an earlier flush or retry.
+        let deadline = Instant::now() + delay;
+
+        if self
+            .timer
+            .as_ref()
+            .is_none_or(|timer| timer.deadline() > deadline)
+        {
+            self.timer = Some(Box::pin(sleep_until(deadline)));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{backoff::Backoff, websocket::WebsocketClientError},
+        relay_rpc::domain::{MessageId, Topic},
+    };
+
+    fn receipt(id: u64) -> Receipt {
+        Receipt {
+            topic: Topic::generate(),
+            message_id: MessageId::new(id),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_failed_batch() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        let mut receipts = ReceiptAggregator::new(
+            ReceiptConfig::new()
+                .with_max_batch_size(2)
+                .with_retry_policy(
+                    RetryPolicy::new()
+                        .with_backoff(backoff.with_jitter(0.0))
+                        .with_max_attempts(2),
+                ),
+        );
+
+        assert!(!receipts.push(receipt(1)));
+        assert!(receipts.push(receipt(2)));
+        assert!(receipts.push(receipt(3)));
+
+        // Keep the requests alive, so that the batches await the response.
+        let requests = receipts.flush();
+        assert_eq!(requests.len(), 2);
+        assert!(receipts.queue.is_empty());
+
+        // The first batch is re-queued after the failure.
+        let failed = vec![receipt(1), receipt(2)];
+        let err = WebsocketClientError::NotConnected.into();
+        assert!(receipts.complete(failed.clone(), 1, Err(err)).is_none());
+        assert_eq!(receipts.retries, [(failed.clone(), 1)]);
+
+        let start = tokio::time::Instant::now();
+        assert!(matches!(receipts.next_event().await, ReceiptEvent::Flush));
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+        // Dropped once the attempts are exhausted.
+        receipts.flush();
+        let err = WebsocketClientError::NotConnected.into();
+        assert!(receipts.complete(failed, 2, Err(err)).is_some());
+        assert!(receipts.retries.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_attempts_per_batch() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        let mut receipts = ReceiptAggregator::new(
+            ReceiptConfig::new()
+                .with_max_batch_size(1)
+                .with_retry_policy(
+                    RetryPolicy::new()
+                        .with_backoff(backoff.with_jitter(0.0))
+                        .with_max_attempts(2),
+                ),
+        );
+
+        receipts.push(receipt(1));
+        receipts.push(receipt(2));
+
+        // Both batches fail at once, e.g. on a disconnect.
+        let fail = |requests: Vec<OutboundRequest>| {
+            for request in requests {
+                let err = WebsocketClientError::NotConnected.into();
+                request.tx.send(Err(err)).ok();
+            }
+        };
+
+        let requests = receipts.flush();
+        assert_eq!(requests.len(), 2);
+        fail(requests);
+
+        for _ in 0..2 {
+            let ReceiptEvent::Delivery((batch, attempt, result)) = receipts.next_event().await
+            else {
+                panic!("expected delivery");
+            };
+
+            assert_eq!(attempt, 1);
+            assert!(receipts.complete(batch, attempt, result).is_none());
+        }
+
+        assert_eq!(receipts.retries.len(), 2);
+
+        // Each batch is retried as its own second attempt, and is dropped once
+        // that one fails too.
+        let requests = receipts.flush();
+        assert_eq!(requests.len(), 2);
+        fail(requests);
+
+        for _ in 0..2 {
+            let ReceiptEvent::Delivery((batch, attempt, result)) = receipts.next_event().await
+            else {
+                panic!("expected delivery");
+            };
+
+            assert_eq!(attempt, 2);
+            assert!(receipts.complete(batch, attempt, result).is_some());
+        }
+
+        assert!(receipts.is_empty());
+    }
+}
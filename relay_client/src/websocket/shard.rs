@@ -0,0 +1,670 @@
+use {
+    super::{
+        AckHandle,
+        BatchResult,
+        Client,
+        ClientConfig,
+        ClientEvent,
+        ClientEventStream,
+        CloseFrame,
+        ConnectionHandler,
+        ConnectionState,
+        EmptyResponseFuture,
+        PublishedMessage,
+        ResponseFuture,
+        SubscriptionResult,
+        SubscriptionStream,
+    },
+    crate::{
+        error::{BoxError, Error},
+        store::{SubscriptionStore, Subscriptions},
+        ClientError,
+        ConnectionOptions,
+    },
+    futures_util::{future::try_join_all, Stream},
+    relay_rpc::{
+        domain::{SubscriptionId, Topic},
+        rpc::{
+            BatchFetchMessages,
+            BatchReceiveMessages,
+            BatchSubscribe,
+            FetchMessages,
+            Publish,
+            Receipt,
+            Subscribe,
+            SubscribeBlocking,
+            SubscriptionError,
+            Unsubscribe,
+        },
+    },
+    std::{
+        pin::Pin,
+        sync::{Arc, Mutex, MutexGuard},
+        task::{Context, Poll},
+        time::Duration,
+    },
+};
+
+/// Handlers for the events of all of the [`ShardedClient`] shards. These
+/// mirror the [`ConnectionHandler`] callbacks, along with the index of the
+/// shard producing the event.
+///
+/// The handler is shared by the shards, so a slow callback delays the events
+/// of all of them.
+pub trait ShardedConnectionHandler: Send + 'static {
+    /// Called when the shard connection to the Relay is established.
+    fn connected(&mut self, _shard: usize) {}
+
+    /// Called when the shard connection is closed.
+    fn disconnected(&mut self, _shard: usize, _frame: Option<CloseFrame>) {}
+
+    /// Called when a reconnection attempt of the shard is scheduled.
+    fn reconnecting(&mut self, _shard: usize, _attempt: u32, _delay: Duration) {}
+
+    /// Called when a reconnection attempt of the shard fails.
+    fn reconnect_failed(&mut self, _shard: usize, _attempt: u32, _error: ClientError) {}
+
+    /// Called when a message is received from the Relay.
+    fn message_received(&mut self, shard: usize, message: PublishedMessage);
+
+    /// Called instead of [`ShardedConnectionHandler::message_received()`] in
+    /// the manual acknowledgement mode. See
+    /// [`ConnectionHandler::message_received_with_ack()`].
+    fn message_received_with_ack(
+        &mut self,
+        shard: usize,
+        message: PublishedMessage,
+        ack: AckHandle,
+    ) {
+        self.message_received(shard, message);
+        ack.ack().ok();
+    }
+
+    /// Called when an inbound error occurs on the shard connection.
+    fn inbound_error(&mut self, _shard: usize, _error: ClientError) {}
+
+    /// Called when an outbound error occurs on the shard connection.
+    fn outbound_error(&mut self, _shard: usize, _error: ClientError) {}
+}
+
+/// [`ConnectionHandler`] of a single shard, forwarding the events to the
+/// shared [`ShardedConnectionHandler`].
+struct ShardHandler<T> {
+    shard: usize,
+    handler: Arc<Mutex<T>>,
+}
+
+impl<T> ShardHandler<T> {
+    fn lock(&self) -> MutexGuard<'_, T> {
+        crate::lock(&self.handler)
+    }
+}
+
+impl<T> ConnectionHandler for ShardHandler<T>
+where
+    T: ShardedConnectionHandler,
+{
+    fn connected(&mut self) {
+        self.lock().connected(self.shard);
+    }
+
+    fn disconnected(&mut self, frame: Option<CloseFrame>) {
+        self.lock().disconnected(self.shard, frame);
+    }
+
+    fn reconnecting(&mut self, attempt: u32, delay: Duration) {
+        self.lock().reconnecting(self.shard, attempt, delay);
+    }
+
+    fn reconnect_failed(&mut self, attempt: u32, error: ClientError) {
+        self.lock().reconnect_failed(self.shard, attempt, error);
+    }
+
+    fn message_received(&mut self, message: PublishedMessage) {
+        self.lock().message_received(self.shard, message);
+    }
+
+    fn message_received_with_ack(&mut self, message: PublishedMessage, ack: AckHandle) {
+        self.lock()
+            .message_received_with_ack(self.shard, message, ack);
+    }
+
+    fn inbound_error(&mut self, error: ClientError) {
+        self.lock().inbound_error(self.shard, error);
+    }
+
+    fn outbound_error(&mut self, error: ClientError) {
+        self.lock().outbound_error(self.shard, error);
+    }
+}
+
+/// Event produced by one of the [`ShardedClient`] shards.
+#[derive(Debug, Clone)]
+pub struct ShardEvent {
+    /// Index of the shard producing the event.
+    pub shard: usize,
+    pub event: ClientEvent,
+}
+
+/// [`Stream`] of the [`ShardEvent`]s of all of the [`ShardedClient`] shards.
+///
+/// The shard streams are polled in turns, so that a busy shard doesn't starve
+/// the others. Cloning the stream creates an independent consumer, see
+/// [`ClientEventStream`].
+#[derive(Debug, Clone)]
+pub struct ShardedEventStream {
+    streams: Vec<(usize, ClientEventStream)>,
+    next: usize,
+}
+
+impl Stream for ShardedEventStream {
+    type Item = ShardEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let count = this.streams.len();
+
+        for offset in 0..count {
+            let idx = (this.next + offset) % count;
+
+            let Some((shard, stream)) = this.streams.get_mut(idx) else {
+                continue;
+            };
+
+            match Pin::new(stream).poll_next(cx) {
+                Poll::Ready(Some(event)) => {
+                    let shard = *shard;
+                    this.next = idx + 1;
+                    return Poll::Ready(Some(ShardEvent { shard, event }));
+                }
+
+                // The client is gone, so the stream no longer produces events.
+                Poll::Ready(None) => {
+                    this.streams.remove(idx);
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+
+                Poll::Pending => {}
+            }
+        }
+
+        if this.streams.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// [`SubscriptionStore`] view of the subscriptions owned by a single shard.
+#[derive(Debug)]
+struct ShardStore {
+    store: Arc<dyn SubscriptionStore>,
+    shard: usize,
+    shards: usize,
+}
+
+impl SubscriptionStore for ShardStore {
+    fn load(&self) -> Result<Subscriptions, BoxError> {
+        let mut subscriptions = self.store.load()?;
+        subscriptions.retain(|topic, _| shard_index(topic, self.shards) == self.shard);
+        Ok(subscriptions)
+    }
+
+    fn insert(&self, subscriptions: Vec<(Topic, SubscriptionId)>) -> Result<(), BoxError> {
+        self.store.insert(subscriptions)
+    }
+
+    fn remove(&self, topics: &[Topic]) -> Result<(), BoxError> {
+        self.store.remove(topics)
+    }
+}
+
+/// The Relay WebSocket RPC client spreading the topics over multiple
+/// connections.
+///
+/// Each shard is a separate [`Client`] with its own connection, and the topics
+/// are assigned to the shards with consistent hashing, so that the assignment
+/// is stable across restarts, and changing the number of shards only moves a
+/// small fraction of the topics. The topic-specific requests are routed to the
+/// shard owning the topic, and the batch requests are split by the shards.
+///
+/// The shards share the [`ClientConfig`]. Each of them reconnects
+/// independently, and the [`SubscriptionStore`] is partitioned, so that each
+/// shard only restores the topics it owns.
+#[derive(Debug, Clone)]
+pub struct ShardedClient {
+    shards: Arc<[Client]>,
+}
+
+impl ShardedClient {
+    /// Creates a new [`ShardedClient`] with the specified number of shards,
+    /// delivering the events of all of them to the handler.
+    pub fn with_config<T>(handler: T, config: ClientConfig, shards: usize) -> Self
+    where
+        T: ShardedConnectionHandler,
+    {
+        let handler = Arc::new(Mutex::new(handler));
+
+        Self::spawn(config, shards, |shard, config| {
+            let handler = ShardHandler {
+                shard,
+                handler: handler.clone(),
+            };
+
+            Client::with_config(handler, config)
+        })
+    }
+
+    /// Creates a new [`ShardedClient`] with the specified number of shards,
+    /// returning it along with the merged [`ShardedEventStream`] of their
+    /// events.
+    pub fn with_config_and_stream(
+        config: ClientConfig,
+        shards: usize,
+    ) -> (Self, ShardedEventStream) {
+        let mut streams = Vec::new();
+
+        let client = Self::spawn(config, shards, |shard, config| {
+            let (client, stream) = Client::with_config_and_stream(config);
+            streams.push((shard, stream));
+            client
+        });
+
+        (client, ShardedEventStream { streams, next: 0 })
+    }
+
+    fn spawn(
+        config: ClientConfig,
+        shards: usize,
+        mut spawn_shard: impl FnMut(usize, ClientConfig) -> Client,
+    ) -> Self {
+        let count = shards.max(1);
+
+        let shards = (0..count)
+            .map(|shard| {
+                let mut config = config.clone();
+
+                if let Some(store) = config.subscription_store.take() {
+                    config.subscription_store = Some(Arc::new(ShardStore {
+                        store,
+                        shard,
+                        shards: count,
+                    }));
+                }
+
+                spawn_shard(shard, config)
+            })
+            .collect();
+
+        Self { shards }
+    }
+
+    /// Returns the shard clients.
+    pub fn shards(&self) -> &[Client] {
+        &self.shards
+    }
+
+    /// Returns the index of the shard owning the topic.
+    pub fn shard_index(&self, topic: &Topic) -> usize {
+        shard_index(topic, self.shards.len())
+    }
+
+    /// Returns the client of the shard owning the topic.
+    #[allow(clippy::indexing_slicing)]
+    pub fn shard(&self, topic: &Topic) -> &Client {
+        // The jump hash is always below the number of shards, and there's always at
+        // least one shard.
+        &self.shards[self.shard_index(topic)]
+    }
+
+    /// Returns the connection states of the shards.
+    pub fn states(&self) -> Vec<ConnectionState> {
+        self.shards
+            .iter()
+            .map(|client| client.state().borrow().clone())
+            .collect()
+    }
+
+    /// Returns `true` if all of the shards are connected to the Relay.
+    pub fn is_connected(&self) -> bool {
+        self.shards.iter().all(Client::is_connected)
+    }
+
+    /// Opens the connections of all of the shards. Fails with the first error,
+    /// in which case some of the shards may still be connected.
+    pub async fn connect(&self, opts: &ConnectionOptions) -> Result<(), ClientError> {
+        try_join_all(self.shards.iter().map(|client| client.connect(opts))).await?;
+        Ok(())
+    }
+
+    /// Closes the connections of all of the shards.
+    pub async fn disconnect(&self) -> Result<(), ClientError> {
+        try_join_all(self.shards.iter().map(Client::disconnect)).await?;
+        Ok(())
+    }
+
+    /// Gracefully closes the connections of all of the shards. See
+    /// [`Client::shutdown()`].
+    pub async fn shutdown(
+        &self,
+        timeout: Duration,
+        frame: impl Into<Option<CloseFrame>>,
+    ) -> Result<(), ClientError> {
+        let frame = frame.into();
+
+        try_join_all(
+            self.shards
+                .iter()
+                .map(|shard| shard.shutdown(timeout, frame.clone())),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Publishes a message on the topic. See [`Client::publish()`].
+    pub fn publish(
+        &self,
+        topic: Topic,
+        message: impl Into<Arc<str>>,
+        attestation: impl Into<Option<Arc<str>>>,
+        tag: u32,
+        ttl: Duration,
+        prompt: bool,
+    ) -> EmptyResponseFuture<Publish> {
+        self.shard(&topic)
+            .publish(topic, message, attestation, tag, ttl, prompt)
+    }
+
+    /// Subscribes on the topic. See [`Client::subscribe()`].
+    pub fn subscribe(&self, topic: Topic) -> ResponseFuture<Subscribe> {
+        self.shard(&topic).subscribe(topic)
+    }
+
+    /// Subscribes on the topic, returning a [`SubscriptionStream`]. See
+    /// [`Client::subscribe_stream()`].
+    pub async fn subscribe_stream(&self, topic: Topic) -> SubscriptionResult<SubscriptionStream> {
+        self.shard(&topic).subscribe_stream(topic).await
+    }
+
+    /// Subscribes on the topic. See [`Client::subscribe_blocking()`].
+    pub fn subscribe_blocking(&self, topic: Topic) -> ResponseFuture<SubscribeBlocking> {
+        self.shard(&topic).subscribe_blocking(topic)
+    }
+
+    /// Unsubscribes from the topic.
+    pub fn unsubscribe(&self, topic: Topic) -> EmptyResponseFuture<Unsubscribe> {
+        self.shard(&topic).unsubscribe(topic)
+    }
+
+    /// Fetches the mailbox messages of the topic.
+    pub fn fetch(&self, topic: Topic) -> ResponseFuture<FetchMessages> {
+        self.shard(&topic).fetch(topic)
+    }
+
+    /// Marks the message as processed on the shard of its topic. See
+    /// [`Client::mark_processed()`].
+    pub async fn mark_processed(&self, receipt: Receipt) -> Result<(), ClientError> {
+        self.shard(&receipt.topic).mark_processed(receipt).await
+    }
+
+    /// Subscribes on multiple topics, with a batch request per shard. The
+    /// subscription IDs are returned in the order of the topics, or the request
+    /// fails with [`ClientError::InvalidBatchResponse`] if a shard doesn't
+    /// respond with one ID per topic.
+    pub async fn batch_subscribe(
+        &self,
+        topics: impl Into<Vec<Topic>>,
+    ) -> BatchResult<BatchSubscribe> {
+        let topics = topics.into();
+        let len = topics.len();
+
+        let responses = try_join_all(self.split(topics, |topic| topic).into_iter().map(
+            |(client, positions, topics)| {
+                let response = client.batch_subscribe(topics);
+                async move { Ok::<_, Error<_>>((positions, response.await?)) }
+            },
+        ))
+        .await?;
+
+        Ok(merge_by_position(responses, len)?)
+    }
+
+    /// Subscribes on multiple topics, with a batch request per shard. See
+    /// [`Client::batch_subscribe_blocking()`]. The results are returned in the
+    /// order of the topics.
+    pub async fn batch_subscribe_blocking(
+        &self,
+        topics: impl Into<Vec<Topic>>,
+    ) -> SubscriptionResult<Vec<SubscriptionResult<SubscriptionId>>> {
+        let topics = topics.into();
+        let len = topics.len();
+
+        let responses = try_join_all(self.split(topics, |topic| topic).into_iter().map(
+            |(client, positions, topics)| {
+                let response = client.batch_subscribe_blocking(topics);
+                async move { Ok::<_, Error<_>>((positions, response.await?)) }
+            },
+        ))
+        .await?;
+
+        Ok(merge_by_position(responses, len)?)
+    }
+
+    /// Unsubscribes from multiple topics, with a batch request per shard.
+    pub async fn batch_unsubscribe(
+        &self,
+        subscriptions: impl Into<Vec<Unsubscribe>>,
+    ) -> Result<(), Error<SubscriptionError>> {
+        let requests = self
+            .split(subscriptions.into(), |subscription| &subscription.topic)
+            .into_iter()
+            .map(|(client, _, subscriptions)| client.batch_unsubscribe(subscriptions));
+
+        try_join_all(requests).await?;
+        Ok(())
+    }
+
+    /// Fetches the mailbox messages of multiple topics, with a batch request
+    /// per shard.
+    pub async fn batch_fetch(
+        &self,
+        topics: impl Into<Vec<Topic>>,
+    ) -> BatchResult<BatchFetchMessages> {
+        let requests = self
+            .split(topics.into(), |topic| topic)
+            .into_iter()
+            .map(|(client, _, topics)| client.batch_fetch(topics));
+
+        Ok(crate::batch::merge_fetch_responses(
+            try_join_all(requests).await?,
+        ))
+    }
+
+    /// Acknowledges the receipt of multiple messages, with a batch request per
+    /// shard. See [`Client::batch_receive()`].
+    pub async fn batch_receive(
+        &self,
+        receipts: impl Into<Vec<Receipt>>,
+    ) -> BatchResult<BatchReceiveMessages> {
+        let requests = self
+            .split(receipts.into(), |receipt| &receipt.topic)
+            .into_iter()
+            .map(|(client, _, receipts)| client.batch_receive(receipts));
+
+        Ok(try_join_all(requests)
+            .await?
+            .into_iter()
+            .all(|result| result))
+    }
+
+    /// Splits the batch items by the shards owning their topics, along with
+    /// their positions in the batch. Empty batches are passed to the first
+    /// shard, so that they're rejected with the same validation error as with
+    /// a single [`Client`].
+    fn split<T>(
+        &self,
+        items: Vec<T>,
+        topic: impl Fn(&T) -> &Topic,
+    ) -> Vec<(&Client, Vec<usize>, Vec<T>)> {
+        let mut groups: Vec<_> = self
+            .shards
+            .iter()
+            .map(|client| (client, Vec::new(), Vec::new()))
+            .collect();
+
+        for (position, item) in items.into_iter().enumerate() {
+            let shard = self.shard_index(topic(&item));
+
+            if let Some((_, positions, items)) = groups.get_mut(shard) {
+                positions.push(position);
+                items.push(item);
+            }
+        }
+
+        if groups.iter().all(|(_, positions, _)| positions.is_empty()) {
+            groups.truncate(1);
+            return groups;
+        }
+
+        groups.retain(|(_, positions, _)| !positions.is_empty());
+        groups
+    }
+}
+
+/// Puts the items of the shard responses back at the positions of the
+/// corresponding items of the batch of `len` items.
+///
+/// Fails with [`ClientError::InvalidBatchResponse`] if a shard response doesn't
+/// have one item per item of its request, or the responses leave any of the
+/// positions unfilled.
+fn merge_by_position<T>(
+    responses: Vec<(Vec<usize>, Vec<T>)>,
+    len: usize,
+) -> Result<Vec<T>, ClientError> {
+    let mut merged: Vec<Option<T>> = std::iter::repeat_with(|| None).take(len).collect();
+
+    for (positions, items) in responses {
+        if items.len() != positions.len() {
+            return Err(ClientError::InvalidBatchResponse {
+                expected: positions.len(),
+                actual: items.len(),
+            });
+        }
+
+        for (position, item) in positions.into_iter().zip(items) {
+            if let Some(slot) = merged.get_mut(position) {
+                *slot = Some(item);
+            }
+        }
+    }
+
+    let filled = merged.iter().filter(|item| item.is_some()).count();
+
+    merged
+        .into_iter()
+        .collect::<Option<_>>()
+        .ok_or(ClientError::InvalidBatchResponse {
+            expected: len,
+            actual: filled,
+        })
+}
+
+/// Returns the index of the shard owning the topic, using the jump consistent
+/// hash of the topic.
+fn shard_index(topic: &Topic, shards: usize) -> usize {
+    jump_hash(fnv1a(topic.value().as_bytes()), shards.max(1))
+}
+
+/// 64-bit FNV-1a hash. Unlike the standard library hashers, the output is
+/// guaranteed to be stable, which keeps the topic assignment stable across
+/// releases.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Jump consistent hash (Lamping and Veach, 2014). Maps the key to one of the
+/// buckets, moving only `1 / buckets` of the keys when a bucket is added.
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let mut bucket = 0;
+    let mut next = 0;
+
+    while next < buckets {
+        bucket = next;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        next =
+            ((bucket as f64 + 1.0) * ((1u64 << 31) as f64 / ((key >> 33) as f64 + 1.0))) as usize;
+    }
+
+    bucket
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consistent_assignment() {
+        let topics: Vec<_> = (0..1000).map(|_| Topic::generate()).collect();
+
+        for topic in &topics {
+            assert_eq!(shard_index(topic, 1), 0);
+            assert_eq!(shard_index(topic, 8), shard_index(topic, 8));
+        }
+
+        let mut counts = [0usize; 8];
+
+        for topic in &topics {
+            if let Some(count) = counts.get_mut(shard_index(topic, 8)) {
+                *count += 1;
+            }
+        }
+
+        assert!(counts.iter().all(|count| (60..190).contains(count)));
+
+        // Adding a shard only moves the topics to the new shard.
+        for topic in &topics {
+            let before = shard_index(topic, 8);
+            let after = shard_index(topic, 9);
+            assert!(after == before || after == 8);
+        }
+
+        let moved = topics
+            .iter()
+            .filter(|topic| shard_index(topic, 9) == 8)
+            .count();
+        assert!(moved < 200);
+    }
+
+    #[test]
+    fn merge_shard_responses() {
+        let responses = vec![(vec![0, 2], vec!["a", "c"]), (vec![1, 3], vec!["b", "d"])];
+        assert_eq!(merge_by_position(responses, 4).unwrap(), [
+            "a", "b", "c", "d"
+        ]);
+
+        // A short shard response.
+        let responses = vec![(vec![0, 2], vec!["a", "c"]), (vec![1, 3], vec!["b"])];
+        assert!(matches!(
+            merge_by_position(responses, 4),
+            Err(ClientError::InvalidBatchResponse {
+                expected: 2,
+                actual: 1
+            })
+        ));
+
+        // Positions not covered by any of the shards.
+        let responses = vec![(vec![0, 2], vec!["a", "c"])];
+        assert!(matches!(
+            merge_by_position(responses, 4),
+            Err(ClientError::InvalidBatchResponse {
+                expected: 4,
+                actual: 2
+            })
+        ));
+    }
+}
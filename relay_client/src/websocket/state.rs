@@ -0,0 +1,42 @@
+use {super::CloseReason, chrono::Utc};
+
+/// State of the [`Client`][super::Client] connection to the Relay. Observable
+/// with [`Client::state()`][super::Client::state].
+#[derive(Debug, Clone, Default)]
+pub enum ConnectionState {
+    /// Not connected, either because [`Client::connect()`] has not been called
+    /// yet, or the connection has been closed with [`Client::disconnect()`].
+    ///
+    /// [`Client::connect()`]: super::Client::connect
+    /// [`Client::disconnect()`]: super::Client::disconnect
+    #[default]
+    Disconnected,
+
+    /// A connection is being established.
+    Connecting,
+
+    /// Connected to the Relay since the specified time.
+    Connected(chrono::DateTime<Utc>),
+
+    /// The client is draining the outstanding requests before closing the
+    /// connection (see [`Client::shutdown()`]).
+    ///
+    /// [`Client::shutdown()`]: super::Client::shutdown
+    ShuttingDown,
+
+    /// The connection was lost and the specified reconnection attempt is
+    /// scheduled or in progress.
+    Reconnecting(u32),
+
+    /// The connection was closed by the Relay or due to an error. If the
+    /// [`ReconnectPolicy`][super::ReconnectPolicy] is configured, followed by
+    /// [`ConnectionState::Reconnecting`] until the policy gives up.
+    Closed(CloseReason),
+}
+
+impl ConnectionState {
+    /// Returns `true` if the client is connected to the Relay.
+    pub fn is_connected(&self) -> bool {
+        matches!(self, Self::Connected(_))
+    }
+}
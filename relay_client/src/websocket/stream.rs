@@ -1,32 +1,57 @@
 use {
     super::{
+        heartbeat::{Heartbeat, HeartbeatAction, RoundTripTime},
         inbound::InboundRequest,
         outbound::{create_request, OutboundRequest, ResponseFuture},
+        transport::{BoxSocket, Socket, Transport, TungsteniteTransport},
         CloseReason,
+        HeartbeatConfig,
         TransportError,
         WebsocketClientError,
     },
-    crate::{error::ClientError, HttpRequest, MessageIdGenerator},
-    futures_util::{stream::FusedStream, SinkExt, Stream, StreamExt},
+    crate::{
+        error::ClientError,
+        metrics,
+        trace::{Outcome, RequestSpan},
+        HttpRequest,
+        MessageIdGenerator,
+    },
+    futures_util::{
+        future::BoxFuture,
+        stream::{FusedStream, FuturesUnordered},
+        FutureExt,
+        SinkExt,
+        Stream,
+        StreamExt,
+    },
     relay_rpc::{
         domain::MessageId,
         rpc::{self, Params, Payload, Response, ServiceRequest, Subscription},
     },
     std::{
         collections::{hash_map::Entry, HashMap},
+        future::Future,
         pin::Pin,
         task::{Context, Poll},
+        time::Duration,
     },
     tokio::{
         net::TcpStream,
         sync::{
-            mpsc,
-            mpsc::{UnboundedReceiver, UnboundedSender},
+            mpsc::{
+                self,
+                error::TrySendError,
+                Receiver,
+                Sender,
+                UnboundedReceiver,
+                UnboundedSender,
+            },
             oneshot,
+            watch,
         },
+        time::Instant,
     },
     tokio_tungstenite::{
-        connect_async,
         tungstenite::{protocol::CloseFrame, Message},
         MaybeTlsStream,
         WebSocketStream,
@@ -35,15 +60,66 @@ use {
 
 pub type SocketStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
+/// Default capacity of the outbound request queue.
+pub const DEFAULT_OUTBOUND_CAPACITY: usize = 1024;
+
 /// Opens a connection to the Relay and returns [`ClientStream`] for the
 /// connection.
 pub async fn create_stream(request: HttpRequest<()>) -> Result<ClientStream, WebsocketClientError> {
-    let (socket, _) = connect_async(request)
-        .await
-        .map_err(Box::new)
-        .map_err(WebsocketClientError::ConnectionFailed)?;
+    create_stream_with_transport(&TungsteniteTransport, request).await
+}
+
+/// Opens a connection to the Relay using the provided [`Transport`] and returns
+/// [`ClientStream`] for the connection.
+pub async fn create_stream_with_transport(
+    transport: &dyn Transport,
+    request: HttpRequest<()>,
+) -> Result<ClientStream, WebsocketClientError> {
+    let socket = transport.connect(request).await?;
+
+    Ok(ClientStream::from_socket(socket))
+}
+
+type ResponseResult = Result<serde_json::Value, ClientError>;
+
+/// Request awaiting the response.
+struct PendingRequest {
+    /// Sends the response to the [`forward_response()`] future of the request.
+    tx: oneshot::Sender<ResponseResult>,
+    method: &'static str,
+    span: RequestSpan,
+    sent_at: Instant,
+}
+
+/// Forwards the response to the caller. Resolves with the request ID if the
+/// caller stops waiting for the response, i.e. the response future is dropped
+/// or times out, so that the pending request can be discarded right away.
+fn forward_response(
+    id: MessageId,
+    mut tx: oneshot::Sender<ResponseResult>,
+) -> (
+    oneshot::Sender<ResponseResult>,
+    BoxFuture<'static, Option<MessageId>>,
+) {
+    let (response_tx, response_rx) = oneshot::channel();
+
+    let forward = async move {
+        tokio::select! {
+            biased;
+
+            result = response_rx => {
+                if let Ok(result) = result {
+                    tx.send(result).ok();
+                }
+
+                None
+            }
 
-    Ok(ClientStream::new(socket))
+            _ = tx.closed() => Some(id),
+        }
+    };
+
+    (response_tx, Box::pin(forward))
 }
 
 /// Possible events produced by the [`ClientStream`].
@@ -79,54 +155,163 @@ pub enum StreamEvent {
 /// For a higher-level interface see [`Client`](crate::client::Client). For an
 /// example usage of the stream see `client::connection` module.
 pub struct ClientStream {
-    socket: SocketStream,
-    outbound_tx: UnboundedSender<Message>,
-    outbound_rx: UnboundedReceiver<Message>,
-    requests: HashMap<MessageId, oneshot::Sender<Result<serde_json::Value, ClientError>>>,
+    socket: BoxSocket,
+    outbound_tx: Sender<Message>,
+    outbound_rx: Receiver<Message>,
+    response_tx: UnboundedSender<Message>,
+    response_rx: UnboundedReceiver<Message>,
+    requests: HashMap<MessageId, PendingRequest>,
+    forwards: FuturesUnordered<BoxFuture<'static, Option<MessageId>>>,
+    pending_requests: watch::Sender<usize>,
     id_generator: MessageIdGenerator,
     close_frame: Option<CloseFrame>,
+    terminated: bool,
+    heartbeat: Option<Heartbeat>,
 }
 
 impl ClientStream {
-    pub fn new(socket: SocketStream) -> Self {
+    pub fn new<S>(socket: S) -> Self
+    where
+        S: Socket,
+    {
+        Self::from_socket(Box::new(socket))
+    }
+
+    /// Creates the stream from an already boxed [`Socket`], such as the one
+    /// returned by the [`Transport`].
+    pub fn from_socket(socket: BoxSocket) -> Self {
         let requests = HashMap::new();
-        let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+        let (outbound_tx, outbound_rx) = mpsc::channel(DEFAULT_OUTBOUND_CAPACITY);
+        let (response_tx, response_rx) = mpsc::unbounded_channel();
         let id_generator = MessageIdGenerator::new();
 
         Self {
             socket,
             outbound_tx,
             outbound_rx,
+            response_tx,
+            response_rx,
             requests,
+            forwards: FuturesUnordered::new(),
+            pending_requests: watch::Sender::new(0),
             id_generator,
             close_frame: None,
+            terminated: false,
+            heartbeat: None,
         }
     }
 
+    /// Sets the capacity of the outbound request queue. When the queue is full,
+    /// [`ClientStream::send_raw()`] fails the request with
+    /// [`ClientError::QueueFull`].
+    ///
+    /// Must be called before sending any requests, as the requests already
+    /// queued are discarded.
+    pub fn with_outbound_capacity(mut self, capacity: usize) -> Self {
+        let (outbound_tx, outbound_rx) = mpsc::channel(capacity.max(1));
+        self.outbound_tx = outbound_tx;
+        self.outbound_rx = outbound_rx;
+        self
+    }
+
+    /// Returns the number of requests that can be queued before the outbound
+    /// queue is full.
+    pub fn outbound_capacity(&self) -> usize {
+        self.outbound_tx.capacity()
+    }
+
+    /// Returns the number of requests awaiting the response, including the ones
+    /// still in the outbound queue.
+    pub fn pending_requests(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Returns a future that resolves once none of the requests is awaiting the
+    /// response.
+    pub(super) fn idle(&self) -> impl Future<Output = ()> + 'static {
+        let mut rx = self.pending_requests.subscribe();
+
+        async move {
+            rx.wait_for(|count| *count == 0).await.ok();
+        }
+    }
+
+    /// Returns a future that resolves once the outbound queue has capacity.
+    pub(super) fn outbound_ready(&self) -> impl Future<Output = ()> + 'static {
+        let tx = self.outbound_tx.clone();
+
+        async move {
+            tx.reserve().await.ok();
+        }
+    }
+
+    /// Enables the websocket ping/pong heartbeat. If a pong is not received
+    /// within the configured timeout, the stream produces
+    /// [`StreamEvent::ConnectionClosed`] with an abnormal close code and the
+    /// timeout reason.
+    pub fn with_heartbeat(self, config: HeartbeatConfig) -> Self {
+        self.with_shared_heartbeat(config, RoundTripTime::default())
+    }
+
+    pub(super) fn with_shared_heartbeat(
+        mut self,
+        config: HeartbeatConfig,
+        rtt: RoundTripTime,
+    ) -> Self {
+        self.heartbeat = Some(Heartbeat::new(config, rtt));
+        self
+    }
+
+    /// Returns the most recent round-trip time measured by the heartbeat.
+    /// Always `None` if the heartbeat is disabled.
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        self.heartbeat.as_ref()?.round_trip_time()
+    }
+
     /// Sends an already serialized [`OutboundRequest`][OutboundRequest] (see
     /// [`create_request()`]).
     pub fn send_raw(&mut self, request: OutboundRequest) {
         let tx = request.tx;
+        let method = request.params.method();
         let id = self.id_generator.next();
+        let span = RequestSpan::new(id, &request.params);
         let request = Payload::Request(rpc::Request::new(id, request.params));
-        let serialized = serde_json::to_string(&request);
 
-        match serialized {
+        let err = match serde_json::to_string(&request) {
             Ok(data) => match self.requests.entry(id) {
-                Entry::Occupied(_) => {
-                    tx.send(Err(ClientError::DuplicateRequestId)).ok();
-                }
+                Entry::Occupied(_) => ClientError::DuplicateRequestId,
+
+                Entry::Vacant(entry) => match self.outbound_tx.try_reserve() {
+                    Ok(permit) => {
+                        let (tx, forward) = forward_response(id, tx);
+
+                        entry.insert(PendingRequest {
+                            tx,
+                            method,
+                            span,
+                            sent_at: Instant::now(),
+                        });
+                        self.forwards.push(forward);
+                        permit.send(Message::Text(data.into()));
+
+                        metrics::request_sent(method);
+                        metrics::outbound_queue_changed(1.0);
+                        self.update_pending_requests();
+
+                        return;
+                    }
 
-                Entry::Vacant(entry) => {
-                    entry.insert(tx);
-                    self.outbound_tx.send(Message::Text(data.into())).ok();
-                }
+                    Err(TrySendError::Full(_)) => ClientError::QueueFull,
+
+                    Err(TrySendError::Closed(_)) => ClientError::ChannelClosed,
+                },
             },
 
-            Err(err) => {
-                tx.send(Err(ClientError::Serialization(err))).ok();
-            }
-        }
+            Err(err) => ClientError::Serialization(err),
+        };
+
+        span.finish(Outcome::Failed(&err), Duration::ZERO);
+        tx.send(Err(err)).ok();
     }
 
     /// Serialize the request into a generic [`OutboundRequest`] and sends it,
@@ -144,7 +329,7 @@ impl ClientStream {
     pub async fn close(&mut self, frame: Option<CloseFrame>) -> Result<(), ClientError> {
         self.close_frame = frame.clone();
         self.socket
-            .close(frame)
+            .send(Message::Close(frame))
             .await
             .map_err(|err| WebsocketClientError::ClosingFailed(Box::new(err)).into())
     }
@@ -171,7 +356,7 @@ impl ClientStream {
                                 match request.params {
                                     Params::Subscription(data) => {
                                         StreamEvent::InboundSubscriptionRequest(
-                                            InboundRequest::new(id, data, self.outbound_tx.clone()),
+                                            InboundRequest::new(id, data, self.response_tx.clone()),
                                         )
                                     }
 
@@ -196,21 +381,32 @@ impl ClientStream {
                                 };
                             }
 
-                            if let Some(tx) = self.requests.remove(&id) {
+                            if let Some(request) = self.requests.remove(&id) {
+                                let elapsed = request.sent_at.elapsed();
+
                                 let result = match response {
                                     Response::Error(response) => {
+                                        metrics::response_received(
+                                            request.method,
+                                            elapsed,
+                                            Some(&response.error),
+                                        );
+                                        request
+                                            .span
+                                            .finish(Outcome::Error(&response.error), elapsed);
+
                                         Err(ClientError::from(response.error))
                                     }
 
-                                    Response::Success(response) => Ok(response.result),
+                                    Response::Success(response) => {
+                                        metrics::response_received(request.method, elapsed, None);
+                                        request.span.finish(Outcome::Success, elapsed);
+                                        Ok(response.result)
+                                    }
                                 };
 
-                                tx.send(result).ok();
-
-                                // Perform compaction if required.
-                                if self.requests.len() * 3 < self.requests.capacity() {
-                                    self.requests.shrink_to_fit();
-                                }
+                                request.tx.send(result).ok();
+                                self.compact_requests();
 
                                 None
                             } else {
@@ -222,7 +418,15 @@ impl ClientStream {
 
                 Message::Close(frame) => {
                     self.close_frame = frame.clone();
-                    Some(StreamEvent::ConnectionClosed(frame.clone()))
+                    Some(self.closed_event())
+                }
+
+                Message::Pong(payload) => {
+                    if let Some(heartbeat) = &mut self.heartbeat {
+                        heartbeat.pong_received(payload);
+                    }
+
+                    None
                 }
 
                 _ => None,
@@ -234,6 +438,53 @@ impl ClientStream {
         }
     }
 
+    /// Removes the pending request whose response future has been dropped or
+    /// has timed out.
+    fn cancel_request(&mut self, id: MessageId) {
+        if let Some(request) = self.requests.remove(&id) {
+            let elapsed = request.sent_at.elapsed();
+            request.span.finish(Outcome::Cancelled, elapsed);
+            self.compact_requests();
+        }
+    }
+
+    fn compact_requests(&mut self) {
+        self.update_pending_requests();
+
+        // Perform compaction if required.
+        if self.requests.len() * 3 < self.requests.capacity() {
+            self.requests.shrink_to_fit();
+        }
+    }
+
+    fn update_pending_requests(&self) {
+        let count = self.requests.len();
+        let previous = self.pending_requests.send_replace(count);
+        metrics::pending_requests_changed(previous, count);
+    }
+
+    /// Marks the stream as terminated and returns the final
+    /// [`StreamEvent::ConnectionClosed`] event.
+    fn closed_event(&mut self) -> StreamEvent {
+        self.terminated = true;
+        StreamEvent::ConnectionClosed(self.close_frame.clone())
+    }
+
+    /// Returns the next outbound message. The responses to the inbound requests
+    /// and the pings take priority over the queued requests.
+    fn poll_next_outbound(&mut self, cx: &mut Context<'_>) -> Option<Message> {
+        if let Poll::Ready(Some(message)) = self.response_rx.poll_recv(cx) {
+            return Some(message);
+        }
+
+        if let Poll::Ready(Some(message)) = self.outbound_rx.poll_recv(cx) {
+            metrics::outbound_queue_changed(-1.0);
+            return Some(message);
+        }
+
+        None
+    }
+
     fn poll_write(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), TransportError>> {
         let mut should_flush = false;
 
@@ -243,7 +494,9 @@ impl ClientStream {
             match self.socket.poll_ready_unpin(cx) {
                 // The sink is ready to accept more data.
                 Poll::Ready(Ok(())) => {
-                    if let Poll::Ready(Some(next_message)) = self.outbound_rx.poll_recv(cx) {
+                    if let Some(next_message) = self.poll_next_outbound(cx) {
+                        metrics::bytes_sent(next_message.len());
+
                         if let Err(err) = self.socket.start_send_unpin(next_message) {
                             return Poll::Ready(Err(Box::new(err)));
                         }
@@ -270,29 +523,61 @@ impl Stream for ClientStream {
     type Item = StreamEvent;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if self.socket.is_terminated() {
+        if self.terminated {
             return Poll::Ready(None);
         }
 
-        while let Poll::Ready(data) = self.socket.poll_next_unpin(cx) {
+        // The socket may terminate without a close frame, e.g. after a transport
+        // error. Make sure the closing event is still produced in that case.
+        if self.socket.is_terminated() {
+            return Poll::Ready(Some(self.closed_event()));
+        }
+
+        let this = &mut *self;
+
+        while let Poll::Ready(Some(forwarded)) = this.forwards.poll_next_unpin(cx) {
+            if let Some(id) = forwarded {
+                this.cancel_request(id);
+            }
+        }
+
+        while let Poll::Ready(data) = this.socket.poll_next_unpin(cx) {
             match data {
                 Some(result) => {
+                    if let Ok(message) = &result {
+                        metrics::bytes_received(message.len());
+                    }
+
                     let result = result.map_err(Box::new);
 
-                    if let Some(event) = self.parse_inbound(result) {
+                    if let Some(event) = this.parse_inbound(result) {
                         return Poll::Ready(Some(event));
                     }
                 }
 
-                None => {
-                    return Poll::Ready(Some(StreamEvent::ConnectionClosed(
-                        self.close_frame.clone(),
-                    )))
+                None => return Poll::Ready(Some(this.closed_event())),
+            }
+        }
+
+        // The heartbeat is only checked once the socket has been drained, so that a
+        // pong received while the stream wasn't polled is processed before the
+        // deadline is considered.
+        if let Some(heartbeat) = &mut this.heartbeat {
+            match heartbeat.poll(cx) {
+                Poll::Ready(HeartbeatAction::Ping(payload)) => {
+                    this.response_tx.send(Message::Ping(payload)).ok();
                 }
+
+                Poll::Ready(HeartbeatAction::TimedOut) => {
+                    this.close_frame = Some(heartbeat.timeout_frame());
+                    return Poll::Ready(Some(this.closed_event()));
+                }
+
+                Poll::Pending => {}
             }
         }
 
-        match self.poll_write(cx) {
+        match this.poll_write(cx) {
             Poll::Ready(Err(error)) => Poll::Ready(Some(StreamEvent::OutboundError(
                 WebsocketClientError::Transport(error).into(),
             ))),
@@ -304,7 +589,7 @@ impl Stream for ClientStream {
 
 impl FusedStream for ClientStream {
     fn is_terminated(&self) -> bool {
-        self.socket.is_terminated()
+        self.terminated
     }
 }
 
@@ -312,11 +597,18 @@ impl Drop for ClientStream {
     fn drop(&mut self) {
         let reason = CloseReason(self.close_frame.take());
 
-        for (_, tx) in self.requests.drain() {
-            tx.send(Err(
-                WebsocketClientError::ConnectionClosed(reason.clone()).into()
-            ))
-            .ok();
+        for (_, request) in self.requests.drain() {
+            let err = WebsocketClientError::ConnectionClosed(reason.clone()).into();
+            request
+                .span
+                .finish(Outcome::Failed(&err), request.sent_at.elapsed());
+            request.tx.send(Err(err)).ok();
         }
+
+        // The responses are ready, so they're forwarded without waiting.
+        while let Some(Some(_)) = self.forwards.next().now_or_never() {}
+
+        self.update_pending_requests();
+        metrics::outbound_queue_changed(-(self.outbound_rx.len() as f64));
     }
 }
@@ -0,0 +1,304 @@
+use {
+    super::{
+        dedup::DedupFilter,
+        inbound::InboundRequest,
+        outbound::OutboundRequest,
+        AckConfig,
+        AckHandle,
+        PublishedMessage,
+    },
+    crate::ClientError,
+    futures_util::{future::BoxFuture, stream::FuturesUnordered, Stream, StreamExt},
+    relay_rpc::{
+        domain::Topic,
+        rpc::{GenericError, Params, Subscription},
+    },
+    std::{
+        collections::{hash_map::Entry, HashMap},
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    },
+    tokio::sync::{mpsc, oneshot},
+};
+
+/// [`Stream`] of the messages published to a single topic. Created by
+/// [`Client::subscribe_stream()`][super::Client::subscribe_stream].
+///
+/// Dropping the stream unsubscribes from the topic, unless the topic has been
+/// subscribed to again, either by another stream or directly.
+///
+/// The stream buffers up to [`ClientConfig::inbound_capacity`] messages, so
+/// that a slow consumer doesn't hold up the rest of the connection. The
+/// messages arriving while the buffer is full are rejected, so that the Relay
+/// redelivers them, and reported as [`ClientError::SubscriptionStreamFull`]
+/// inbound errors. The other streams of the topic receive the redelivered
+/// messages again.
+///
+/// In the manual acknowledgement mode (see [`ClientConfig::manual_ack`]), the
+/// messages are acknowledged once taken from the stream. Use
+/// [`SubscriptionStream::with_ack()`] to acknowledge them after processing.
+///
+/// [`ClientConfig::inbound_capacity`]: super::ClientConfig::inbound_capacity
+/// [`ClientConfig::manual_ack`]: super::ClientConfig::manual_ack
+#[derive(Debug)]
+pub struct SubscriptionStream {
+    topic: Topic,
+    rx: mpsc::Receiver<RoutedMessage>,
+}
+
+/// Message delivered to the [`SubscriptionStream`], along with its
+/// acknowledgement handle in the manual acknowledgement mode.
+pub(super) type RoutedMessage = (PublishedMessage, Option<AckHandle>);
+
+impl SubscriptionStream {
+    pub(super) fn new(topic: Topic, rx: mpsc::Receiver<RoutedMessage>) -> Self {
+        Self { topic, rx }
+    }
+
+    /// The topic of the subscription.
+    pub fn topic(&self) -> &Topic {
+        &self.topic
+    }
+
+    /// Turns the stream into an [`AckSubscriptionStream`], yielding the
+    /// messages along with their [`AckHandle`]s.
+    pub fn with_ack(self) -> AckSubscriptionStream {
+        AckSubscriptionStream { inner: self }
+    }
+}
+
+impl Stream for SubscriptionStream {
+    type Item = PublishedMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx).map(|item| {
+            item.map(|(message, ack)| {
+                if let Some(ack) = ack {
+                    ack.ack().ok();
+                }
+
+                message
+            })
+        })
+    }
+}
+
+/// [`SubscriptionStream`] yielding the messages along with their
+/// [`AckHandle`]s. Created by [`SubscriptionStream::with_ack()`].
+///
+/// In the manual acknowledgement mode, each message has to be acknowledged
+/// within [`AckConfig::timeout`], same as the messages delivered to the
+/// [`ConnectionHandler`][super::ConnectionHandler]. The streams of the same
+/// topic share the handle, so the first acknowledgement wins. Otherwise, the
+/// messages are acknowledged on arrival, and the handles are already resolved.
+#[derive(Debug)]
+pub struct AckSubscriptionStream {
+    inner: SubscriptionStream,
+}
+
+impl AckSubscriptionStream {
+    /// The topic of the subscription.
+    pub fn topic(&self) -> &Topic {
+        &self.inner.topic
+    }
+}
+
+impl Stream for AckSubscriptionStream {
+    type Item = (PublishedMessage, AckHandle);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.rx.poll_recv(cx).map(|item| {
+            item.map(|(message, ack)| {
+                let ack = ack.unwrap_or_else(|| AckHandle::resolved(message.message_id));
+                (message, ack)
+            })
+        })
+    }
+}
+
+/// Routes the inbound subscription messages to the [`SubscriptionStream`]s of
+/// their topics.
+pub(super) struct Router {
+    routes: HashMap<Topic, Vec<mpsc::Sender<RoutedMessage>>>,
+    closed: FuturesUnordered<BoxFuture<'static, Topic>>,
+
+    /// Number of the successful subscribe requests of each topic since it was
+    /// last unsubscribed. Shared with the futures awaiting the responses.
+    refs: Arc<Mutex<HashMap<Topic, usize>>>,
+
+    /// Manual acknowledgement of the routed messages, if enabled.
+    ack: Option<AckConfig>,
+
+    /// De-duplication filter forgetting the rejected messages, so that their
+    /// redeliveries aren't dropped.
+    dedup: Option<DedupFilter>,
+}
+
+impl Router {
+    pub(super) fn new(ack: Option<AckConfig>, dedup: Option<DedupFilter>) -> Self {
+        Self {
+            routes: HashMap::new(),
+            closed: FuturesUnordered::new(),
+            refs: Default::default(),
+            ack,
+            dedup,
+        }
+    }
+
+    /// Counts the topics of the outbound subscribe request once the relay
+    /// responds successfully, and stops counting the unsubscribed ones right
+    /// away. Returns the future forwarding the response to the original
+    /// request, if any.
+    pub(super) fn track(
+        &mut self,
+        request: &mut OutboundRequest,
+    ) -> Option<BoxFuture<'static, Result<(), ClientError>>> {
+        let topics = match &request.params {
+            Params::Subscribe(data) => vec![data.topic.clone()],
+            Params::SubscribeBlocking(data) => vec![data.topic.clone()],
+            Params::BatchSubscribe(data) => data.topics.clone(),
+            Params::BatchSubscribeBlocking(data) => data.topics.clone(),
+
+            Params::Unsubscribe(data) => {
+                crate::lock(&self.refs).remove(&data.topic);
+                return None;
+            }
+
+            Params::BatchUnsubscribe(data) => {
+                let mut refs = crate::lock(&self.refs);
+
+                for data in &data.subscriptions {
+                    refs.remove(&data.topic);
+                }
+
+                return None;
+            }
+
+            _ => return None,
+        };
+
+        let refs = self.refs.clone();
+        let (tx, rx) = oneshot::channel();
+        let original_tx = std::mem::replace(&mut request.tx, tx);
+
+        Some(Box::pin(async move {
+            let response = rx.await.unwrap_or(Err(ClientError::ChannelClosed));
+
+            // Count the topics before forwarding the response, so that a stream
+            // registered right after is accounted for.
+            if response.is_ok() {
+                let mut refs = crate::lock(&refs);
+
+                for topic in topics {
+                    *refs.entry(topic).or_default() += 1;
+                }
+            }
+
+            original_tx.send(response).ok();
+
+            Ok(())
+        }))
+    }
+
+    pub(super) fn add(&mut self, topic: Topic, tx: mpsc::Sender<RoutedMessage>) {
+        self.closed.push(Box::pin({
+            let tx = tx.clone();
+            let topic = topic.clone();
+
+            async move {
+                tx.closed().await;
+                topic
+            }
+        }));
+
+        self.routes.entry(topic).or_default().push(tx);
+    }
+
+    /// Delivers the message to the streams of its topic without waiting, and
+    /// acknowledges it, unless in the manual acknowledgement mode. Returns the
+    /// request back if there are no streams for the topic.
+    ///
+    /// Fails with [`ClientError::SubscriptionStreamFull`] if any of the streams
+    /// is full, in which case the message is rejected, so that the Relay
+    /// redelivers it.
+    pub(super) fn route(
+        &self,
+        request: InboundRequest<Subscription>,
+    ) -> Result<Option<InboundRequest<Subscription>>, ClientError> {
+        let topic = request.data().data.topic.clone();
+
+        let Some(routes) = self.routes.get(&topic) else {
+            return Ok(Some(request));
+        };
+
+        let message = PublishedMessage::from_request(&request);
+
+        // The streams share the handle, so that only the first acknowledgement is
+        // sent.
+        let (ack, request) = match &self.ack {
+            Some(config) => (Some(AckHandle::new(request, config)), None),
+            None => (None, Some(request)),
+        };
+
+        let mut overflow = false;
+
+        for tx in routes {
+            overflow |= matches!(
+                tx.try_send((message.clone(), ack.clone())),
+                Err(mpsc::error::TrySendError::Full(_))
+            );
+        }
+
+        if !overflow {
+            if let Some(request) = request {
+                request.respond(Ok(true)).ok();
+            }
+
+            return Ok(None);
+        }
+
+        if let Some(dedup) = &self.dedup {
+            dedup.forget(message.message_id, &message.message);
+        }
+
+        match (request, ack) {
+            (Some(request), _) => request.respond(Err(GenericError::Unknown)).ok(),
+            (_, Some(ack)) => ack.nack(GenericError::Unknown).ok(),
+            _ => None,
+        };
+
+        Err(ClientError::SubscriptionStreamFull(topic))
+    }
+
+    /// Waits for a stream to be dropped, releasing the subscription it holds.
+    /// Resolves with the topic once nothing else holds a subscription to it.
+    pub(super) async fn next_closed(&mut self) -> Option<Topic> {
+        loop {
+            let topic = self.closed.next().await?;
+
+            let Entry::Occupied(mut entry) = self.routes.entry(topic) else {
+                continue;
+            };
+
+            let count = entry.get().len();
+            entry.get_mut().retain(|tx| !tx.is_closed());
+            let released = count - entry.get().len();
+
+            let topic = if entry.get().is_empty() {
+                entry.remove_entry().0
+            } else {
+                entry.key().clone()
+            };
+
+            // The topic may have been unsubscribed while the stream was alive.
+            if let Entry::Occupied(mut refs) = crate::lock(&self.refs).entry(topic) {
+                *refs.get_mut() = refs.get().saturating_sub(released);
+
+                if *refs.get() == 0 {
+                    return Some(refs.remove_entry().0);
+                }
+            }
+        }
+    }
+}
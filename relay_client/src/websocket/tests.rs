@@ -0,0 +1,1981 @@
+use {
+    super::*,
+    crate::{
+        auth::AuthProvider,
+        backoff::Backoff,
+        error::{BoxError, ErrorKind},
+        rate_limit::{MethodClass, RateLimit, RateLimitMode, RateLimiter, RateLimiterConfig},
+        retry::RetryPolicy,
+        store::{MemorySubscriptionStore, SubscriptionStore, Subscriptions},
+        HttpRequest,
+        MessageIdGenerator,
+    },
+    futures_util::{future::BoxFuture, stream, SinkExt, Stream, StreamExt},
+    relay_rpc::{
+        auth::{ed25519_dalek::SigningKey, AuthToken},
+        rpc::{
+            BatchReceiveMessages,
+            Error as RpcError,
+            ErrorResponse,
+            FetchResponse,
+            GenericError,
+            Params,
+            Payload,
+            Receipt,
+            Request,
+            Response,
+            SubscriptionData,
+            SubscriptionResult,
+            SuccessfulResponse,
+            MAX_SUBSCRIPTION_BATCH_SIZE,
+        },
+    },
+    std::{
+        collections::HashSet,
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+    tokio::{
+        io::{AsyncRead, AsyncWrite},
+        net::TcpListener,
+        sync::{broadcast, mpsc::UnboundedReceiver, Notify},
+        task::JoinHandle,
+    },
+    tokio_tungstenite::{
+        accept_async,
+        tungstenite::{protocol::frame::coding::CloseCode, Message},
+        WebSocketStream,
+    },
+};
+
+const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Topic the [`MockRelay`] refuses to subscribe to.
+const REJECTED_TOPIC: &str = "rejected";
+
+/// Minimal in-process Relay that accepts websocket connections, responds to
+/// RPC requests and can drop the connections on demand.
+struct MockRelay {
+    address: String,
+    requests: UnboundedReceiver<Params>,
+    responses: UnboundedReceiver<Response>,
+    kill: Arc<Notify>,
+    freeze: Arc<Notify>,
+    connections: Arc<AtomicUsize>,
+    messages: broadcast::Sender<Request>,
+    server: JoinHandle<()>,
+}
+
+impl MockRelay {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("ws://{}", listener.local_addr().unwrap());
+
+        let accept = stream::unfold(listener, |listener| async move {
+            loop {
+                let (socket, _) = listener.accept().await.ok()?;
+
+                if let Ok(ws) = accept_async(socket).await {
+                    return Some((ws, listener));
+                }
+            }
+        });
+
+        Self::spawn(address, accept)
+    }
+
+    /// Starts the relay accepting the connections from the returned
+    /// [`MemoryTransport`].
+    fn start_in_memory() -> (Self, MemoryTransport) {
+        let (transport, listener) = MemoryTransport::new();
+
+        let accept = stream::unfold(listener, |mut listener| async move {
+            let (_, ws) = listener.accept().await?;
+            Some((ws, listener))
+        });
+
+        (Self::spawn("ws://in-memory".to_owned(), accept), transport)
+    }
+
+    fn spawn<S>(
+        address: String,
+        accept: impl Stream<Item = WebSocketStream<S>> + Send + 'static,
+    ) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (requests_tx, requests) = mpsc::unbounded_channel();
+        let (responses_tx, responses) = mpsc::unbounded_channel();
+        let kill = Arc::new(Notify::new());
+        let freeze = Arc::new(Notify::new());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (messages, _) = broadcast::channel(16);
+
+        let server = tokio::spawn({
+            let kill = kill.clone();
+            let freeze = freeze.clone();
+            let connections = connections.clone();
+            let messages = messages.clone();
+
+            async move {
+                let mut accept = std::pin::pin!(accept);
+
+                while let Some(ws) = accept.next().await {
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(serve_connection(
+                        ws,
+                        requests_tx.clone(),
+                        responses_tx.clone(),
+                        kill.clone(),
+                        freeze.clone(),
+                        messages.subscribe(),
+                    ));
+                }
+            }
+        });
+
+        Self {
+            address,
+            requests,
+            responses,
+            kill,
+            freeze,
+            connections,
+            messages,
+            server,
+        }
+    }
+
+    fn opts(&self) -> ConnectionOptions {
+        let key = SigningKey::generate(&mut rand::thread_rng());
+        let auth = AuthToken::new("http://example.com")
+            .aud(&self.address)
+            .as_jwt(&key)
+            .unwrap();
+
+        ConnectionOptions::new("test_project_id", auth).with_address(&self.address)
+    }
+
+    fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Drops all of the currently open connections without a close frame.
+    fn drop_connections(&self) {
+        self.kill.notify_waiters();
+    }
+
+    /// Stops processing the currently open connections while keeping them
+    /// open, simulating a half-open connection.
+    fn freeze_connections(&self) {
+        self.freeze.notify_waiters();
+    }
+
+    /// Stops accepting new connections and drops the existing ones.
+    fn shutdown(&self) {
+        self.server.abort();
+        self.drop_connections();
+    }
+
+    /// Delivers a subscription message to all of the currently open
+    /// connections.
+    fn publish(&self, topic: Topic, message: &str) -> MessageId {
+        let id = MessageIdGenerator::new().next();
+        let params = Params::Subscription(Subscription {
+            id: SubscriptionId::generate(),
+            data: SubscriptionData {
+                topic,
+                message: message.into(),
+                attestation: None,
+                published_at: 0,
+                tag: 0,
+            },
+        });
+
+        self.messages.send(Request::new(id, params)).ok();
+
+        id
+    }
+
+    async fn next_request(&mut self) -> Params {
+        tokio::time::timeout(EVENT_TIMEOUT, self.requests.recv())
+            .await
+            .expect("timed out waiting for request")
+            .expect("relay stopped")
+    }
+
+    async fn next_response(&mut self) -> Response {
+        tokio::time::timeout(EVENT_TIMEOUT, self.responses.recv())
+            .await
+            .expect("timed out waiting for response")
+            .expect("relay stopped")
+    }
+}
+
+async fn serve_connection<S>(
+    mut ws: WebSocketStream<S>,
+    requests: mpsc::UnboundedSender<Params>,
+    responses: mpsc::UnboundedSender<Response>,
+    kill: Arc<Notify>,
+    freeze: Arc<Notify>,
+    mut messages: broadcast::Receiver<Request>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Register for the notifications upfront, so that they're not missed while
+    // the connection is busy processing a message.
+    let mut kill = std::pin::pin!(kill.notified());
+    let mut freeze = std::pin::pin!(freeze.notified());
+
+    loop {
+        tokio::select! {
+            message = ws.next() => {
+                let request = match message {
+                    Some(Ok(Message::Text(data))) => match serde_json::from_str(&data) {
+                        Ok(Payload::Request(request)) => request,
+
+                        Ok(Payload::Response(response)) => {
+                            responses.send(response).ok();
+                            continue;
+                        }
+
+                        _ => continue,
+                    },
+
+                    Some(Ok(_)) => continue,
+
+                    _ => break,
+                };
+
+                let response = mock_response(&request);
+                requests.send(request.params).ok();
+
+                let data = serde_json::to_string(&Payload::Response(response)).unwrap();
+
+                if ws.send(Message::Text(data.into())).await.is_err() {
+                    break;
+                }
+            }
+
+            Ok(request) = messages.recv() => {
+                let data = serde_json::to_string(&Payload::Request(request)).unwrap();
+
+                if ws.send(Message::Text(data.into())).await.is_err() {
+                    break;
+                }
+            }
+
+            _ = &mut kill => break,
+
+            _ = &mut freeze => {
+                kill.await;
+                break;
+            }
+        }
+    }
+}
+
+fn mock_response(request: &Request) -> Response {
+    let rejected = match &request.params {
+        Params::Subscribe(data) => data.topic.as_ref() == REJECTED_TOPIC,
+        Params::BatchSubscribe(data) => data
+            .topics
+            .iter()
+            .any(|topic| topic.as_ref() == REJECTED_TOPIC),
+        _ => false,
+    };
+
+    if rejected {
+        let err = RpcError::Handler(GenericError::Unknown);
+        return Response::Error(ErrorResponse::new(request.id, err));
+    }
+
+    let result = match &request.params {
+        Params::Subscribe(_) | Params::SubscribeBlocking(_) => {
+            serde_json::to_value(SubscriptionId::generate()).unwrap()
+        }
+
+        // Echo the topics back as the subscription IDs, so that the order of the
+        // responses can be verified.
+        Params::BatchSubscribe(data) => serde_json::to_value(
+            data.topics
+                .iter()
+                .map(|topic| SubscriptionId::new(topic.value().clone()))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap(),
+
+        Params::BatchSubscribeBlocking(data) => serde_json::to_value(
+            data.topics
+                .iter()
+                .map(|_| SubscriptionResult::Id(SubscriptionId::generate()))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap(),
+
+        Params::FetchMessages(_) | Params::BatchFetchMessages(_) => {
+            serde_json::to_value(FetchResponse {
+                messages: Vec::new(),
+                has_more: false,
+            })
+            .unwrap()
+        }
+
+        _ => serde_json::Value::Bool(true),
+    };
+
+    Response::Success(SuccessfulResponse::new(request.id, result))
+}
+
+#[derive(Debug)]
+enum HandlerEvent {
+    Connected,
+    Disconnected(Option<CloseFrame>),
+    Reconnecting(u32),
+    ReconnectFailed(u32),
+}
+
+struct TestHandler {
+    tx: mpsc::UnboundedSender<HandlerEvent>,
+}
+
+impl TestHandler {
+    fn new() -> (Self, UnboundedReceiver<HandlerEvent>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self { tx }, rx)
+    }
+}
+
+impl ConnectionHandler for TestHandler {
+    fn connected(&mut self) {
+        self.tx.send(HandlerEvent::Connected).ok();
+    }
+
+    fn disconnected(&mut self, frame: Option<CloseFrame>) {
+        self.tx.send(HandlerEvent::Disconnected(frame)).ok();
+    }
+
+    fn reconnecting(&mut self, attempt: u32, _delay: Duration) {
+        self.tx.send(HandlerEvent::Reconnecting(attempt)).ok();
+    }
+
+    fn reconnect_failed(&mut self, attempt: u32, _error: ClientError) {
+        self.tx.send(HandlerEvent::ReconnectFailed(attempt)).ok();
+    }
+
+    fn message_received(&mut self, _message: PublishedMessage) {}
+}
+
+async fn next_event(events: &mut UnboundedReceiver<HandlerEvent>) -> HandlerEvent {
+    tokio::time::timeout(EVENT_TIMEOUT, events.recv())
+        .await
+        .expect("timed out waiting for handler event")
+        .expect("handler dropped")
+}
+
+fn fast_reconnect() -> ReconnectPolicy {
+    ReconnectPolicy::new().with_backoff(
+        Backoff::new(Duration::from_millis(10), Duration::from_millis(50)).with_jitter(0.0),
+    )
+}
+
+#[tokio::test]
+async fn reconnects_and_resubscribes() {
+    let mut relay = MockRelay::start().await;
+    let (handler, mut events) = TestHandler::new();
+    let client = Client::with_config(
+        handler,
+        ClientConfig::new().with_reconnect(fast_reconnect()),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    // Creating a topic doesn't subscribe to it.
+    client.create_topic(Topic::generate()).await.unwrap();
+    assert!(matches!(relay.next_request().await, Params::CreateTopic(_)));
+
+    let topic = Topic::generate();
+    client.subscribe(topic.clone()).await.unwrap();
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+
+    relay.drop_connections();
+
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Disconnected(_)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Reconnecting(1)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+    assert_eq!(relay.connections(), 2);
+
+    match relay.next_request().await {
+        Params::BatchSubscribe(data) => assert_eq!(data.topics, vec![topic]),
+        params => panic!("unexpected request: {params:?}"),
+    }
+}
+
+#[tokio::test]
+async fn resubscribes_active_topics() {
+    let mut relay = MockRelay::start().await;
+    let (handler, mut events) = TestHandler::new();
+
+    // Slow enough to unsubscribe before reconnecting.
+    let reconnect = ReconnectPolicy::new().with_backoff(
+        Backoff::new(Duration::from_millis(200), Duration::from_millis(200)).with_jitter(0.0),
+    );
+    let client = Client::with_config(handler, ClientConfig::new().with_reconnect(reconnect));
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    let topic = Topic::generate();
+    let unsubscribed = Topic::generate();
+
+    for topic in [&topic, &unsubscribed] {
+        client.subscribe(topic.clone()).await.unwrap();
+        assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+    }
+
+    // The topics the relay refuses to subscribe to are not replayed.
+    assert!(client.subscribe(Topic::from(REJECTED_TOPIC)).await.is_err());
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+
+    relay.drop_connections();
+
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Disconnected(_)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Reconnecting(1)
+    ));
+
+    // Unsubscribing while disconnected stops replaying the topic.
+    assert!(client.unsubscribe(unsubscribed).await.is_err());
+
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    match relay.next_request().await {
+        Params::BatchSubscribe(data) => assert_eq!(data.topics, vec![topic]),
+        params => panic!("unexpected request: {params:?}"),
+    }
+}
+
+#[tokio::test]
+async fn auth_token_per_connection() {
+    let relay = MockRelay::start().await;
+    let (handler, mut events) = TestHandler::new();
+    let client = Client::with_config(
+        handler,
+        ClientConfig::new().with_reconnect(fast_reconnect()),
+    );
+
+    let key = SigningKey::generate(&mut rand::thread_rng());
+    let calls = Arc::new(AtomicUsize::new(0));
+    let opts = relay.opts().with_auth_provider(AuthProvider::new({
+        let calls = calls.clone();
+        let address = relay.address.clone();
+
+        move || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            AuthToken::new("http://example.com")
+                .aud(&address)
+                .as_jwt(&key)
+                .map_err(Into::into)
+        }
+    }));
+
+    client.connect(&opts).await.unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    relay.drop_connections();
+
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Disconnected(_)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Reconnecting(1)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn reconnect_gives_up_after_max_attempts() {
+    let relay = MockRelay::start().await;
+    let (handler, mut events) = TestHandler::new();
+    let client = Client::with_config(
+        handler,
+        ClientConfig::new().with_reconnect(fast_reconnect().with_max_attempts(2)),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    relay.shutdown();
+
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Disconnected(_)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Reconnecting(1)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::ReconnectFailed(1)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Reconnecting(2)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::ReconnectFailed(2)
+    ));
+
+    let next = tokio::time::timeout(Duration::from_millis(200), events.recv()).await;
+    assert!(next.is_err(), "unexpected event: {next:?}");
+}
+
+#[tokio::test]
+async fn disconnect_disables_reconnect() {
+    let relay = MockRelay::start().await;
+    let (handler, mut events) = TestHandler::new();
+    let client = Client::with_config(
+        handler,
+        ClientConfig::new().with_reconnect(fast_reconnect()),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    client.disconnect().await.unwrap();
+
+    let next = tokio::time::timeout(Duration::from_millis(200), events.recv()).await;
+    assert!(next.is_err(), "unexpected event: {next:?}");
+    assert_eq!(relay.connections(), 1);
+}
+
+#[tokio::test]
+async fn graceful_shutdown() {
+    let relay = MockRelay::start().await;
+    let (handler, mut events) = TestHandler::new();
+    let client = Client::with_config(
+        handler,
+        ClientConfig::new().with_reconnect(fast_reconnect()),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    // The in-flight request completes before the connection is closed.
+    let subscribe = client.subscribe(Topic::generate());
+    let frame = CloseFrame {
+        code: CloseCode::Away,
+        reason: "deploy".into(),
+    };
+    client.shutdown(EVENT_TIMEOUT, frame).await.unwrap();
+    assert!(subscribe.await.is_ok());
+
+    match next_event(&mut events).await {
+        HandlerEvent::Disconnected(Some(frame)) => assert_eq!(frame.code, CloseCode::Away),
+        event => panic!("unexpected event: {event:?}"),
+    }
+
+    assert!(matches!(
+        *client.state().borrow(),
+        ConnectionState::Disconnected
+    ));
+    assert!(matches!(
+        client.subscribe(Topic::generate()).await,
+        Err(Error::Client(ClientError::ChannelClosed))
+    ));
+
+    // The connection is not restored, and the handler is dropped with the
+    // connection task.
+    let next = tokio::time::timeout(Duration::from_millis(200), events.recv()).await;
+    assert!(matches!(next, Ok(None)), "unexpected event: {next:?}");
+    assert_eq!(relay.connections(), 1);
+}
+
+#[tokio::test]
+async fn shutdown_timeout() {
+    let relay = MockRelay::start().await;
+    let (handler, mut events) = TestHandler::new();
+    let client = Client::with_config(handler, ClientConfig::new());
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    relay.freeze_connections();
+
+    let subscribe = client.subscribe(Topic::generate());
+    let shutdown = tokio::spawn({
+        let client = client.clone();
+        async move { client.shutdown(Duration::from_millis(100), None).await }
+    });
+
+    // New requests are rejected while draining.
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(matches!(
+        *client.state().borrow(),
+        ConnectionState::ShuttingDown
+    ));
+    assert!(matches!(
+        client.subscribe(Topic::generate()).await,
+        Err(Error::Client(ClientError::ShuttingDown))
+    ));
+
+    shutdown.await.unwrap().unwrap();
+    assert!(matches!(
+        subscribe.await,
+        Err(Error::Client(ClientError::WebsocketClient(
+            WebsocketClientError::ConnectionClosed(_)
+        )))
+    ));
+}
+
+#[tokio::test]
+async fn receipt_batching() {
+    let mut relay = MockRelay::start().await;
+    let (handler, _events) = TestHandler::new();
+    let client = Client::with_config(
+        handler,
+        ClientConfig::new().with_receipt_batching(
+            ReceiptConfig::new()
+                .with_max_batch_size(2)
+                .with_flush_interval(Duration::from_millis(50)),
+        ),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+
+    let receipts = (1..=3)
+        .map(|id| Receipt {
+            topic: Topic::generate(),
+            message_id: MessageId::new(id),
+        })
+        .collect::<Vec<_>>();
+
+    for receipt in &receipts {
+        client.mark_processed(receipt.clone()).await.unwrap();
+    }
+
+    // The full batch is sent right away, and the rest once the interval passes.
+    for expected in receipts.chunks(2) {
+        assert_eq!(
+            relay.next_request().await,
+            Params::BatchReceiveMessages(BatchReceiveMessages {
+                receipts: expected.to_vec(),
+            })
+        );
+    }
+}
+
+#[tokio::test]
+async fn heartbeat_detects_dead_connection() {
+    let relay = MockRelay::start().await;
+    let (handler, mut events) = TestHandler::new();
+    let client = Client::with_config(
+        handler,
+        ClientConfig::new().with_heartbeat(HeartbeatConfig::new(
+            Duration::from_millis(50),
+            Duration::from_millis(100),
+        )),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    tokio::time::timeout(EVENT_TIMEOUT, async {
+        while client.round_trip_time().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("round-trip time not measured");
+
+    relay.freeze_connections();
+
+    match next_event(&mut events).await {
+        HandlerEvent::Disconnected(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::Abnormal);
+            assert!(frame.reason.starts_with("Heartbeat timeout"));
+        }
+
+        event => panic!("unexpected event: {event:?}"),
+    }
+}
+
+#[tokio::test]
+async fn heartbeat_survives_stalled_consumer() {
+    let (transport, mut listener) = MemoryTransport::new();
+    let request = HttpRequest::builder().uri("ws://relay").body(()).unwrap();
+    let mut stream = create_stream_with_transport(&transport, request)
+        .await
+        .unwrap()
+        .with_heartbeat(HeartbeatConfig::new(
+            Duration::from_millis(20),
+            Duration::from_millis(50),
+        ));
+
+    // Reading the server side of the connection answers the pings. The relay
+    // starts answering only once the consumer below has stalled.
+    let (_, mut server) = listener.accept().await.unwrap();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        while server.next().await.is_some() {}
+    });
+
+    async fn poll_stream(stream: &mut ClientStream, duration: Duration) -> Option<StreamEvent> {
+        tokio::time::timeout(duration, stream.next())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    // Let the first ping go out.
+    assert!(poll_stream(&mut stream, Duration::from_millis(30))
+        .await
+        .is_none());
+
+    // Stall the consumer for longer than the heartbeat timeout, while the pong
+    // arrives in the socket.
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    assert!(poll_stream(&mut stream, Duration::from_millis(10))
+        .await
+        .is_none());
+    assert!(stream.round_trip_time().is_some());
+}
+
+#[tokio::test]
+async fn request_timeout() {
+    let relay = MockRelay::start().await;
+    let (handler, mut events) = TestHandler::new();
+    let client = Client::with_config(
+        handler,
+        ClientConfig::new().with_request_timeout(Duration::from_millis(100)),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    relay.freeze_connections();
+
+    // Default timeout from the client config.
+    let result = client.subscribe(Topic::generate()).await;
+    assert!(matches!(
+        result,
+        Err(Error::Client(ClientError::RequestTimeout))
+    ));
+
+    // Per-request override, shorter than the default.
+    let result = tokio::time::timeout(
+        Duration::from_millis(50),
+        client
+            .publish(
+                Topic::generate(),
+                "message",
+                None,
+                0,
+                Duration::from_secs(60),
+                false,
+            )
+            .timeout(Duration::from_millis(10)),
+    )
+    .await
+    .expect("request timeout not applied");
+    assert!(matches!(
+        result,
+        Err(Error::Client(ClientError::RequestTimeout))
+    ));
+}
+
+#[tokio::test]
+async fn cancelled_request_frees_slot() {
+    let (transport, mut listener) = MemoryTransport::new();
+    let request = HttpRequest::builder().uri("ws://relay").body(()).unwrap();
+    let mut stream = create_stream_with_transport(&transport, request)
+        .await
+        .unwrap();
+
+    // Keep the connection open, without ever responding.
+    let _server = listener.accept().await.unwrap();
+
+    async fn poll_stream(stream: &mut ClientStream) -> Option<Option<StreamEvent>> {
+        tokio::time::timeout(Duration::from_millis(10), stream.next())
+            .await
+            .ok()
+    }
+
+    // Dropped response future.
+    drop(stream.send(Subscribe {
+        topic: Topic::generate(),
+    }));
+    assert_eq!(stream.pending_requests(), 1);
+    assert!(poll_stream(&mut stream).await.is_none());
+    assert_eq!(stream.pending_requests(), 0);
+
+    // Timed out response future.
+    let response = stream
+        .send(Subscribe {
+            topic: Topic::generate(),
+        })
+        .timeout(Duration::from_millis(10));
+
+    let (result, _) = tokio::join!(response, poll_stream(&mut stream));
+    assert!(matches!(
+        result,
+        Err(Error::Client(ClientError::RequestTimeout))
+    ));
+
+    assert!(poll_stream(&mut stream).await.is_none());
+    assert_eq!(stream.pending_requests(), 0);
+}
+
+#[tokio::test]
+async fn request_queue_full() {
+    // The listener never completes the websocket handshake, which stalls the
+    // connection task and prevents it from draining the request queue.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("ws://{}", listener.local_addr().unwrap());
+    let key = SigningKey::generate(&mut rand::thread_rng());
+    let auth = AuthToken::new("http://example.com").as_jwt(&key).unwrap();
+    let opts = ConnectionOptions::new("test_project_id", auth).with_address(address);
+
+    let (handler, _events) = TestHandler::new();
+    let client = Client::with_config(handler, ClientConfig::new().with_request_capacity(1));
+
+    tokio::spawn({
+        let client = client.clone();
+        async move { client.connect(&opts).await }
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let _queued = client
+        .try_send(Subscribe {
+            topic: Topic::generate(),
+        })
+        .unwrap();
+
+    let result = client.try_send(Subscribe {
+        topic: Topic::generate(),
+    });
+    assert!(matches!(result, Err(ClientError::QueueFull)));
+
+    // The regular send waits for the queue capacity instead of failing.
+    let result = tokio::time::timeout(
+        Duration::from_millis(50),
+        client.subscribe(Topic::generate()),
+    )
+    .await;
+    assert!(result.is_err());
+}
+
+/// [`Transport`] holding the connection attempts until the gate is opened.
+#[derive(Debug)]
+struct GatedTransport {
+    inner: MemoryTransport,
+    gate: Arc<Notify>,
+}
+
+impl Transport for GatedTransport {
+    fn connect(
+        &self,
+        request: HttpRequest<()>,
+    ) -> BoxFuture<'static, Result<BoxSocket, WebsocketClientError>> {
+        let gate = self.gate.clone();
+        let connect = self.inner.connect(request);
+
+        Box::pin(async move {
+            gate.notified().await;
+            connect.await
+        })
+    }
+}
+
+#[tokio::test]
+async fn request_queue_full_sends_in_background() {
+    let (mut relay, transport) = MockRelay::start_in_memory();
+    let gate = Arc::new(Notify::new());
+    let transport = GatedTransport {
+        inner: transport,
+        gate: gate.clone(),
+    };
+    let (client, _events) = Client::with_config_and_stream(
+        ClientConfig::new()
+            .with_transport(transport)
+            .with_request_capacity(1),
+    );
+
+    // Stall the connection task, so that the request queue fills up.
+    tokio::spawn({
+        let client = client.clone();
+        let opts = relay.opts();
+        async move { client.connect(&opts).await }
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let _queued = client
+        .try_send(Subscribe {
+            topic: Topic::generate(),
+        })
+        .unwrap();
+
+    // Sent once the queue has capacity, even though the response future is
+    // dropped right away.
+    drop(client.publish(
+        Topic::generate(),
+        "message",
+        None,
+        0,
+        Duration::from_secs(60),
+        false,
+    ));
+
+    gate.notify_one();
+
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+    assert!(matches!(relay.next_request().await, Params::Publish(_)));
+}
+
+async fn next_client_event(events: &mut ClientEventStream) -> ClientEvent {
+    tokio::time::timeout(EVENT_TIMEOUT, events.next())
+        .await
+        .expect("timed out waiting for client event")
+        .expect("event stream closed")
+}
+
+#[tokio::test]
+async fn event_stream_fan_out() {
+    let relay = MockRelay::start().await;
+    let (client, mut first) = Client::new_with_stream();
+    let mut second = first.clone();
+
+    client.connect(&relay.opts()).await.unwrap();
+
+    for events in [&mut first, &mut second] {
+        assert!(matches!(
+            next_client_event(events).await,
+            ClientEvent::Connected
+        ));
+    }
+
+    let topic = Topic::generate();
+    relay.publish(topic.clone(), "message");
+
+    for events in [&mut first, &mut second] {
+        match next_client_event(events).await {
+            ClientEvent::Message(message) => {
+                assert_eq!(message.topic, topic);
+                assert_eq!(message.message.as_ref(), "message");
+            }
+
+            event => panic!("unexpected event: {event:?}"),
+        }
+    }
+
+    // Dropped consumers don't block the remaining ones.
+    drop(second);
+    relay.drop_connections();
+
+    loop {
+        match next_client_event(&mut first).await {
+            ClientEvent::Disconnected(_) => break,
+            ClientEvent::InboundError(_) => continue,
+            event => panic!("unexpected event: {event:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn manual_ack() {
+    let mut relay = MockRelay::start().await;
+    let (client, mut events) = Client::with_config_and_stream(
+        ClientConfig::new().with_manual_ack(
+            AckConfig::new()
+                .with_timeout(Duration::from_millis(100))
+                .with_timeout_action(AckTimeoutAction::Ack),
+        ),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_client_event(&mut events).await,
+        ClientEvent::Connected
+    ));
+
+    // Explicit nack.
+    let id = relay.publish(Topic::generate(), "message");
+
+    let ClientEvent::MessageWithAck(message, ack) = next_client_event(&mut events).await else {
+        panic!("expected message with ack handle");
+    };
+    assert_eq!(message.message_id, id);
+    assert!(!ack.is_resolved());
+
+    ack.nack(GenericError::Unknown).unwrap();
+    assert!(ack.is_resolved());
+
+    // Only the first acknowledgement is sent.
+    ack.ack().unwrap();
+
+    match relay.next_response().await {
+        Response::Error(response) => assert_eq!(response.id, id),
+        response => panic!("unexpected response: {response:?}"),
+    }
+
+    // Acknowledged automatically after the timeout.
+    let id = relay.publish(Topic::generate(), "message");
+
+    let ClientEvent::MessageWithAck(_, ack) = next_client_event(&mut events).await else {
+        panic!("expected message with ack handle");
+    };
+
+    match relay.next_response().await {
+        Response::Success(response) => assert_eq!(response.id, id),
+        response => panic!("unexpected response: {response:?}"),
+    }
+    assert!(ack.is_resolved());
+
+    // The messages routed to the streams are acknowledged by the consumer.
+    let topic = Topic::generate();
+    let mut stream = client
+        .subscribe_stream(topic.clone())
+        .await
+        .unwrap()
+        .with_ack();
+    let id = relay.publish(topic.clone(), "message");
+
+    let (message, ack) = tokio::time::timeout(EVENT_TIMEOUT, stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.message_id, id);
+    assert!(!ack.is_resolved());
+
+    ack.nack(GenericError::Unknown).unwrap();
+
+    match relay.next_response().await {
+        Response::Error(response) => assert_eq!(response.id, id),
+        response => panic!("unexpected response: {response:?}"),
+    }
+
+    // Acknowledged once taken from the stream without the handles.
+    let topic = Topic::generate();
+    let mut stream = client.subscribe_stream(topic.clone()).await.unwrap();
+    let id = relay.publish(topic, "message");
+
+    tokio::time::timeout(EVENT_TIMEOUT, stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+
+    match relay.next_response().await {
+        Response::Success(response) => assert_eq!(response.id, id),
+        response => panic!("unexpected response: {response:?}"),
+    }
+}
+
+#[tokio::test]
+async fn in_memory_transport() {
+    let (mut relay, transport) = MockRelay::start_in_memory();
+    let (client, mut events) =
+        Client::with_config_and_stream(ClientConfig::new().with_transport(transport));
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_client_event(&mut events).await,
+        ClientEvent::Connected
+    ));
+    assert_eq!(relay.connections(), 1);
+
+    let topic = Topic::generate();
+    client.subscribe(topic.clone()).await.unwrap();
+
+    match relay.next_request().await {
+        Params::Subscribe(data) => assert_eq!(data.topic, topic),
+        params => panic!("unexpected request: {params:?}"),
+    }
+
+    relay.publish(topic.clone(), "message");
+
+    match next_client_event(&mut events).await {
+        ClientEvent::Message(message) => assert_eq!(message.topic, topic),
+        event => panic!("unexpected event: {event:?}"),
+    }
+
+    client.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn subscription_stream_routing() {
+    let mut relay = MockRelay::start().await;
+    let (client, mut events) = Client::new_with_stream();
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_client_event(&mut events).await,
+        ClientEvent::Connected
+    ));
+
+    let first_topic = Topic::generate();
+    let second_topic = Topic::generate();
+    let mut first = client.subscribe_stream(first_topic.clone()).await.unwrap();
+    let mut second = client.subscribe_stream(second_topic.clone()).await.unwrap();
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+
+    relay.publish(second_topic.clone(), "second");
+    relay.publish(first_topic.clone(), "first");
+
+    let message = tokio::time::timeout(EVENT_TIMEOUT, first.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.topic, first_topic);
+    assert_eq!(message.message.as_ref(), "first");
+
+    let message = tokio::time::timeout(EVENT_TIMEOUT, second.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.topic, second_topic);
+
+    // Messages of the topics without streams are delivered to the consumers of
+    // the client events.
+    let other_topic = Topic::generate();
+    relay.publish(other_topic.clone(), "other");
+
+    match next_client_event(&mut events).await {
+        ClientEvent::Message(message) => assert_eq!(message.topic, other_topic),
+        event => panic!("unexpected event: {event:?}"),
+    }
+
+    // Dropping the stream unsubscribes from the topic.
+    drop(first);
+
+    match relay.next_request().await {
+        Params::Unsubscribe(data) => assert_eq!(data.topic, first_topic),
+        params => panic!("unexpected request: {params:?}"),
+    }
+
+    // Neither a failed subscription nor dropping the stream of a topic that's
+    // also subscribed to directly unsubscribes from the topic.
+    let result = client.subscribe_stream(Topic::from(REJECTED_TOPIC)).await;
+    assert!(result.is_err());
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+
+    client.subscribe(second_topic.clone()).await.unwrap();
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+    drop(second);
+
+    let request = tokio::time::timeout(Duration::from_millis(100), relay.requests.recv()).await;
+    assert!(request.is_err(), "unexpected request: {request:?}");
+}
+
+#[tokio::test]
+async fn slow_subscription_stream() {
+    let mut relay = MockRelay::start().await;
+    let (client, mut events) = Client::with_config_and_stream(
+        ClientConfig::new()
+            .with_inbound_capacity(1)
+            .with_dedup(DedupConfig::new()),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_client_event(&mut events).await,
+        ClientEvent::Connected
+    ));
+
+    let topic = Topic::generate();
+    let mut stream = client.subscribe_stream(topic.clone()).await.unwrap();
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+
+    // The stream isn't read, so the second message overflows it.
+    let first = relay.publish(topic.clone(), "first");
+    let second = relay.publish(topic.clone(), "second");
+
+    match next_client_event(&mut events).await {
+        ClientEvent::InboundError(err) => assert!(matches!(
+            &*err,
+            ClientError::SubscriptionStreamFull(full) if *full == topic
+        )),
+        event => panic!("unexpected event: {event:?}"),
+    }
+
+    // The overflowing message is rejected, so that the relay redelivers it.
+    match relay.next_response().await {
+        Response::Success(response) => assert_eq!(response.id, first),
+        response => panic!("unexpected response: {response:?}"),
+    }
+
+    match relay.next_response().await {
+        Response::Error(response) => assert_eq!(response.id, second),
+        response => panic!("unexpected response: {response:?}"),
+    }
+
+    // The rest of the connection isn't held up by the stream.
+    let other_topic = Topic::generate();
+    relay.publish(other_topic.clone(), "other");
+
+    match next_client_event(&mut events).await {
+        ClientEvent::Message(message) => assert_eq!(message.topic, other_topic),
+        event => panic!("unexpected event: {event:?}"),
+    }
+
+    let message = tokio::time::timeout(EVENT_TIMEOUT, stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.message.as_ref(), "first");
+
+    // The redelivered message isn't dropped as a duplicate.
+    relay.publish(topic, "second");
+
+    let message = tokio::time::timeout(EVENT_TIMEOUT, stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.message.as_ref(), "second");
+}
+
+#[tokio::test]
+async fn connection_state() {
+    let relay = MockRelay::start().await;
+    let (handler, mut events) = TestHandler::new();
+    let client = Client::with_config(
+        handler,
+        ClientConfig::new().with_reconnect(fast_reconnect().with_max_attempts(1)),
+    );
+    let mut state = client.state();
+
+    assert!(matches!(*state.borrow(), ConnectionState::Disconnected));
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(client.is_connected());
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    relay.shutdown();
+
+    let reconnecting = tokio::time::timeout(
+        EVENT_TIMEOUT,
+        state.wait_for(|state| matches!(state, ConnectionState::Reconnecting(1))),
+    )
+    .await
+    .unwrap()
+    .is_ok();
+    assert!(reconnecting);
+
+    // The only reconnection attempt fails, and the connection stays closed.
+    let closed = tokio::time::timeout(
+        EVENT_TIMEOUT,
+        state.wait_for(|state| matches!(state, ConnectionState::Closed(_))),
+    )
+    .await
+    .unwrap()
+    .is_ok();
+    assert!(closed);
+    assert!(!client.is_connected());
+}
+
+#[tokio::test]
+async fn subscription_batching() {
+    let mut relay = MockRelay::start().await;
+    let (client, _events) = Client::with_config_and_stream(
+        ClientConfig::new().with_subscription_batching(Duration::from_millis(50)),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+
+    let topics = (0..3).map(|_| Topic::generate()).collect::<Vec<_>>();
+    let ids = futures_util::future::try_join_all(
+        topics.iter().map(|topic| client.subscribe(topic.clone())),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(ids.len(), 3);
+    assert!(ids
+        .iter()
+        .all(|id| ids.iter().filter(|other| *other == id).count() == 1));
+
+    match relay.next_request().await {
+        Params::BatchSubscribe(data) => assert_eq!(data.topics, topics),
+        params => panic!("unexpected request: {params:?}"),
+    }
+
+    futures_util::future::try_join_all(
+        topics.iter().map(|topic| client.unsubscribe(topic.clone())),
+    )
+    .await
+    .unwrap();
+
+    match relay.next_request().await {
+        Params::BatchUnsubscribe(data) => assert_eq!(data.subscriptions.len(), 3),
+        params => panic!("unexpected request: {params:?}"),
+    }
+
+    // The requests for the same topic are sent once and share the response.
+    let topic = Topic::generate();
+    let (first, second) = tokio::join!(
+        client.subscribe(topic.clone()),
+        client.subscribe(topic.clone())
+    );
+    assert_eq!(first.unwrap(), second.unwrap());
+
+    match relay.next_request().await {
+        Params::BatchSubscribe(data) => assert_eq!(data.topics, vec![topic]),
+        params => panic!("unexpected request: {params:?}"),
+    }
+
+    // As well as the error response of the batch request.
+    let (first, second) = tokio::join!(
+        client.subscribe(Topic::generate()),
+        client.subscribe(Topic::from(REJECTED_TOPIC))
+    );
+
+    for result in [first, second] {
+        match result {
+            Err(err @ Error::Response(_)) => assert_eq!(err.kind(), ErrorKind::Handler),
+            result => panic!("unexpected result: {result:?}"),
+        }
+    }
+
+    assert!(matches!(
+        relay.next_request().await,
+        Params::BatchSubscribe(_)
+    ));
+
+    // Other requests are not coalesced.
+    client.fetch(Topic::generate()).await.unwrap();
+    assert!(matches!(
+        relay.next_request().await,
+        Params::FetchMessages(_)
+    ));
+}
+
+#[tokio::test]
+async fn batch_chunking() {
+    let mut relay = MockRelay::start().await;
+    let (client, _events) =
+        Client::with_config_and_stream(ClientConfig::new().with_batch_concurrency(2));
+
+    client.connect(&relay.opts()).await.unwrap();
+
+    let topics = (0..MAX_SUBSCRIPTION_BATCH_SIZE * 2 + 10)
+        .map(|_| Topic::generate())
+        .collect::<Vec<_>>();
+
+    let ids = client.batch_subscribe(topics.clone()).await.unwrap();
+    let expected = topics
+        .iter()
+        .map(|topic| SubscriptionId::new(topic.value().clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(ids, expected);
+
+    let mut sent = Vec::new();
+
+    for expected_len in [MAX_SUBSCRIPTION_BATCH_SIZE, MAX_SUBSCRIPTION_BATCH_SIZE, 10] {
+        match relay.next_request().await {
+            Params::BatchSubscribe(data) => {
+                assert_eq!(data.topics.len(), expected_len);
+                sent.extend(data.topics);
+            }
+            params => panic!("unexpected request: {params:?}"),
+        }
+    }
+
+    assert_eq!(sent, topics);
+
+    // Batches within the limit are sent as is.
+    client
+        .batch_fetch(topics.iter().take(3).cloned().collect::<Vec<_>>())
+        .await
+        .unwrap();
+    match relay.next_request().await {
+        Params::BatchFetchMessages(data) => assert_eq!(data.topics.len(), 3),
+        params => panic!("unexpected request: {params:?}"),
+    }
+}
+
+#[tokio::test]
+async fn offline_queue() {
+    let mut relay = MockRelay::start().await;
+    let (client, _events) = Client::with_config_and_stream(
+        ClientConfig::new().with_offline_queue(OfflineQueueConfig::new()),
+    );
+
+    let topic = Topic::generate();
+    let publish = |message: &'static str| {
+        client.publish(
+            topic.clone(),
+            message,
+            None,
+            0,
+            Duration::from_secs(300),
+            false,
+        )
+    };
+
+    let first = tokio::spawn(publish("first"));
+    let second = tokio::spawn(publish("second"));
+
+    // Only the publish requests are queued.
+    assert!(matches!(
+        client.subscribe(topic.clone()).await,
+        Err(Error::Client(ClientError::WebsocketClient(
+            WebsocketClientError::NotConnected
+        )))
+    ));
+
+    client.connect(&relay.opts()).await.unwrap();
+
+    for expected in ["first", "second"] {
+        match relay.next_request().await {
+            Params::Publish(data) => assert_eq!(&*data.message, expected),
+            params => panic!("unexpected request: {params:?}"),
+        }
+    }
+
+    first.await.unwrap().unwrap();
+    second.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn subscription_store() {
+    let mut relay = MockRelay::start().await;
+    let store = MemorySubscriptionStore::new();
+    let (client, _events) =
+        Client::with_config_and_stream(ClientConfig::new().with_subscription_store(store.clone()));
+
+    // Subscription left from the previous session.
+    let stored_topic = Topic::generate();
+    store
+        .insert(vec![(stored_topic.clone(), SubscriptionId::generate())])
+        .unwrap();
+
+    client.connect(&relay.opts()).await.unwrap();
+
+    match relay.next_request().await {
+        Params::BatchSubscribe(data) => assert_eq!(data.topics, vec![stored_topic.clone()]),
+        params => panic!("unexpected request: {params:?}"),
+    }
+
+    let topic = Topic::generate();
+    let id = client.subscribe(topic.clone()).await.unwrap();
+
+    let stored = wait_stored(&store, |stored| stored.contains_key(&topic)).await;
+    assert_eq!(stored.get(&topic), Some(&id));
+
+    // The mock relay responds with the topics as the IDs of the batch
+    // subscriptions.
+    assert_eq!(
+        stored.get(&stored_topic),
+        Some(&SubscriptionId::new(stored_topic.value().clone()))
+    );
+
+    client.unsubscribe(topic.clone()).await.unwrap();
+    wait_stored(&store, |stored| !stored.contains_key(&topic)).await;
+}
+
+#[tokio::test]
+async fn subscription_store_after_disconnect() {
+    let mut relay = MockRelay::start().await;
+    let store = MemorySubscriptionStore::new();
+    let (client, _events) =
+        Client::with_config_and_stream(ClientConfig::new().with_subscription_store(store.clone()));
+
+    let stored_topic = Topic::generate();
+    store
+        .insert(vec![(stored_topic.clone(), SubscriptionId::generate())])
+        .unwrap();
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        relay.next_request().await,
+        Params::BatchSubscribe(_)
+    ));
+
+    // Disconnect before the subscription is written to the store.
+    let topic = Topic::generate();
+    client.subscribe(topic.clone()).await.unwrap();
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+    client.disconnect().await.unwrap();
+
+    // The store is loaded again, including the pending changes.
+    client.connect(&relay.opts()).await.unwrap();
+
+    match relay.next_request().await {
+        Params::BatchSubscribe(data) => assert_eq!(
+            data.topics.into_iter().collect::<HashSet<_>>(),
+            HashSet::from([stored_topic, topic])
+        ),
+        params => panic!("unexpected request: {params:?}"),
+    }
+}
+
+#[tokio::test]
+async fn subscription_store_rejected_replay() {
+    let mut relay = MockRelay::start().await;
+    let store = MemorySubscriptionStore::new();
+    let (handler, mut events) = TestHandler::new();
+    let client = Client::with_config(
+        handler,
+        ClientConfig::new()
+            .with_reconnect(fast_reconnect())
+            .with_subscription_store(store.clone()),
+    );
+
+    // Subscription the relay no longer accepts.
+    store
+        .insert(vec![(
+            Topic::from(REJECTED_TOPIC),
+            SubscriptionId::generate(),
+        )])
+        .unwrap();
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+    assert!(matches!(
+        relay.next_request().await,
+        Params::BatchSubscribe(_)
+    ));
+
+    wait_stored(&store, |stored| stored.is_empty()).await;
+
+    relay.drop_connections();
+
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Disconnected(_)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Reconnecting(1)
+    ));
+    assert!(matches!(
+        next_event(&mut events).await,
+        HandlerEvent::Connected
+    ));
+
+    // Nothing is re-subscribed after the reconnect.
+    client.subscribe(Topic::generate()).await.unwrap();
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+}
+
+/// Waits for the subscriptions written to the store in the background to
+/// satisfy the condition.
+async fn wait_stored(
+    store: &impl SubscriptionStore,
+    condition: impl Fn(&Subscriptions) -> bool,
+) -> Subscriptions {
+    tokio::time::timeout(EVENT_TIMEOUT, async {
+        loop {
+            let stored = store.load().unwrap();
+
+            if condition(&stored) {
+                return stored;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for store")
+}
+
+/// [`SubscriptionStore`] taking a long time to write the changes.
+#[derive(Debug, Default)]
+struct SlowStore {
+    inner: MemorySubscriptionStore,
+}
+
+impl SubscriptionStore for SlowStore {
+    fn load(&self) -> Result<Subscriptions, BoxError> {
+        self.inner.load()
+    }
+
+    fn insert(&self, subscriptions: Vec<(Topic, SubscriptionId)>) -> Result<(), BoxError> {
+        std::thread::sleep(Duration::from_millis(500));
+        self.inner.insert(subscriptions)
+    }
+
+    fn remove(&self, topics: &[Topic]) -> Result<(), BoxError> {
+        std::thread::sleep(Duration::from_millis(500));
+        self.inner.remove(topics)
+    }
+}
+
+#[tokio::test]
+async fn slow_subscription_store() {
+    let mut relay = MockRelay::start().await;
+    let (client, _events) = Client::with_config_and_stream(
+        ClientConfig::new().with_subscription_store(SlowStore::default()),
+    );
+
+    client.connect(&relay.opts()).await.unwrap();
+
+    // Requests are served while the subscriptions are being written.
+    for _ in 0..5 {
+        let started = tokio::time::Instant::now();
+
+        client.subscribe(Topic::generate()).await.unwrap();
+        assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+        client.fetch(Topic::generate()).await.unwrap();
+        assert!(matches!(
+            relay.next_request().await,
+            Params::FetchMessages(_)
+        ));
+
+        assert!(started.elapsed() < Duration::from_millis(250));
+    }
+}
+
+#[tokio::test]
+async fn dedup() {
+    let mut relay = MockRelay::start().await;
+    let (client, mut events) =
+        Client::with_config_and_stream(ClientConfig::new().with_dedup(DedupConfig::new()));
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert!(matches!(
+        next_client_event(&mut events).await,
+        ClientEvent::Connected
+    ));
+
+    let topic = Topic::generate();
+    client.subscribe(topic.clone()).await.unwrap();
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+
+    // Same message delivered twice, with different request IDs.
+    relay.publish(topic.clone(), "first");
+    relay.publish(topic.clone(), "first");
+    relay.publish(topic.clone(), "second");
+
+    for expected in ["first", "second"] {
+        match next_client_event(&mut events).await {
+            ClientEvent::Message(message) => assert_eq!(&*message.message, expected),
+            event => panic!("unexpected event: {event:?}"),
+        }
+    }
+
+    // The duplicate is still acknowledged.
+    for _ in 0..3 {
+        assert!(matches!(relay.next_response().await, Response::Success(_)));
+    }
+}
+
+#[tokio::test]
+async fn rate_limiter() {
+    let mut relay = MockRelay::start().await;
+    let limiter = RateLimiter::new(
+        RateLimiterConfig::new()
+            .with_limit(
+                MethodClass::Subscribe,
+                RateLimit::new(1, Duration::from_secs(60)),
+            )
+            .with_mode(RateLimitMode::FailFast),
+    );
+    let (client, _events) =
+        Client::with_config_and_stream(ClientConfig::new().with_rate_limiter(limiter));
+
+    client.connect(&relay.opts()).await.unwrap();
+
+    client.subscribe(Topic::generate()).await.unwrap();
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+
+    assert!(matches!(
+        client.subscribe(Topic::generate()).await,
+        Err(Error::Client(ClientError::RateLimited))
+    ));
+    assert!(matches!(
+        client.try_send(Subscribe {
+            topic: Topic::generate()
+        }),
+        Err(ClientError::RateLimited)
+    ));
+
+    // Other method classes have separate budgets.
+    client.fetch(Topic::generate()).await.unwrap();
+    assert!(matches!(
+        relay.next_request().await,
+        Params::FetchMessages(_)
+    ));
+}
+
+#[tokio::test]
+async fn rate_limiter_wait() {
+    let mut relay = MockRelay::start().await;
+    let limiter = RateLimiter::new(RateLimiterConfig::new().with_limit(
+        MethodClass::Publish,
+        RateLimit::new(1, Duration::from_millis(50)),
+    ));
+    let (client, _events) =
+        Client::with_config_and_stream(ClientConfig::new().with_rate_limiter(limiter));
+
+    client.connect(&relay.opts()).await.unwrap();
+
+    // The deferred requests are sent even though the response futures are
+    // dropped right away.
+    for _ in 0..3 {
+        drop(client.publish(
+            Topic::generate(),
+            "message",
+            None,
+            0,
+            Duration::from_secs(60),
+            false,
+        ));
+    }
+
+    for _ in 0..3 {
+        assert!(matches!(relay.next_request().await, Params::Publish(_)));
+    }
+}
+
+#[tokio::test]
+async fn rate_limiter_queue_full() {
+    // The listener never completes the websocket handshake, which stalls the
+    // connection task and prevents it from draining the request queue.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("ws://{}", listener.local_addr().unwrap());
+    let key = SigningKey::generate(&mut rand::thread_rng());
+    let auth = AuthToken::new("http://example.com").as_jwt(&key).unwrap();
+    let opts = ConnectionOptions::new("test_project_id", auth).with_address(address);
+
+    let limiter = RateLimiter::new(RateLimiterConfig::new().with_limit(
+        MethodClass::Subscribe,
+        RateLimit::new(2, Duration::from_secs(60)),
+    ));
+    let (client, _events) = Client::with_config_and_stream(
+        ClientConfig::new()
+            .with_request_capacity(1)
+            .with_rate_limiter(limiter.clone()),
+    );
+
+    tokio::spawn({
+        let client = client.clone();
+        async move { client.connect(&opts).await }
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let _queued = client
+        .try_send(Subscribe {
+            topic: Topic::generate(),
+        })
+        .unwrap();
+
+    for _ in 0..3 {
+        let result = client.try_send(Subscribe {
+            topic: Topic::generate(),
+        });
+        assert!(matches!(result, Err(ClientError::QueueFull)));
+    }
+
+    // The rejected requests haven't consumed the budget.
+    assert!(limiter.try_acquire(MethodClass::Subscribe).is_ok());
+    assert!(limiter.try_acquire(MethodClass::Subscribe).is_err());
+}
+
+#[tokio::test]
+async fn retry_policy() {
+    let mut relay = MockRelay::start().await;
+    let (client, _events) = Client::with_config_and_stream(
+        ClientConfig::new().with_retry_policy(
+            RetryPolicy::new()
+                .with_backoff(
+                    Backoff::new(Duration::from_millis(20), Duration::from_millis(20))
+                        .with_jitter(0.0),
+                )
+                .with_max_attempts(50),
+        ),
+    );
+
+    // Not retried, since the method is not idempotent.
+    assert!(matches!(
+        client.create_topic(Topic::generate()).await,
+        Err(Error::Client(ClientError::WebsocketClient(
+            WebsocketClientError::NotConnected
+        )))
+    ));
+
+    let fetch = tokio::spawn(client.fetch(Topic::generate()));
+
+    // Let the first attempts fail.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    client.connect(&relay.opts()).await.unwrap();
+
+    fetch.await.unwrap().unwrap();
+    assert!(matches!(
+        relay.next_request().await,
+        Params::FetchMessages(_)
+    ));
+
+    // Sent even though the response future is dropped right away.
+    drop(client.publish(
+        Topic::generate(),
+        "message",
+        None,
+        0,
+        Duration::from_secs(60),
+        false,
+    ));
+    assert!(matches!(relay.next_request().await, Params::Publish(_)));
+}
+
+#[tokio::test]
+async fn sharded_client() {
+    let mut relay = MockRelay::start().await;
+    let (client, mut events) = ShardedClient::with_config_and_stream(ClientConfig::new(), 3);
+
+    client.connect(&relay.opts()).await.unwrap();
+    assert_eq!(relay.connections(), 3);
+    assert!(client.is_connected());
+
+    let mut connected = Vec::new();
+
+    for _ in 0..3 {
+        let event = tokio::time::timeout(EVENT_TIMEOUT, events.next())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(event.event, ClientEvent::Connected));
+        connected.push(event.shard);
+    }
+
+    connected.sort();
+    assert_eq!(connected, [0, 1, 2]);
+
+    // The mock relay responds with the topics as the subscription IDs.
+    let topics: Vec<_> = (0..30).map(|_| Topic::generate()).collect();
+    let ids = client.batch_subscribe(topics.clone()).await.unwrap();
+    assert_eq!(
+        ids,
+        topics
+            .iter()
+            .map(|topic| SubscriptionId::new(topic.value().clone()))
+            .collect::<Vec<_>>()
+    );
+
+    // Each of the shards only subscribes on its own topics.
+    let mut subscribed = Vec::new();
+
+    while subscribed.len() < topics.len() {
+        match relay.next_request().await {
+            Params::BatchSubscribe(data) => {
+                let shards: HashSet<_> = data
+                    .topics
+                    .iter()
+                    .map(|topic| client.shard_index(topic))
+                    .collect();
+                assert_eq!(shards.len(), 1);
+                subscribed.extend(data.topics);
+            }
+
+            params => panic!("unexpected request: {params:?}"),
+        }
+    }
+
+    assert_eq!(subscribed.len(), topics.len());
+
+    let results = client
+        .batch_subscribe_blocking(topics.clone())
+        .await
+        .unwrap();
+    assert_eq!(results.len(), topics.len());
+    assert!(results.iter().all(Result::is_ok));
+
+    let receipts: Vec<_> = topics
+        .iter()
+        .map(|topic| Receipt {
+            topic: topic.clone(),
+            message_id: MessageIdGenerator::new().next(),
+        })
+        .collect();
+    assert!(client.batch_receive(receipts).await.unwrap());
+
+    // The batches of both requests are split by the shards too.
+    let mut routed = 0;
+
+    while routed < topics.len() * 2 {
+        let topics = match relay.next_request().await {
+            Params::BatchSubscribeBlocking(data) => data.topics,
+            Params::BatchReceiveMessages(data) => data
+                .receipts
+                .into_iter()
+                .map(|receipt| receipt.topic)
+                .collect(),
+            params => panic!("unexpected request: {params:?}"),
+        };
+
+        let shards: HashSet<_> = topics
+            .iter()
+            .map(|topic| client.shard_index(topic))
+            .collect();
+        assert_eq!(shards.len(), 1);
+        routed += topics.len();
+    }
+
+    let topic = Topic::generate();
+    client.subscribe(topic.clone()).await.unwrap();
+    assert!(matches!(relay.next_request().await, Params::Subscribe(_)));
+    assert!(client.shard(&topic).is_connected());
+}
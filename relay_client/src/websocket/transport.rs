@@ -0,0 +1,203 @@
+use {
+    super::{RawTransportError, WebsocketClientError},
+    crate::{
+        proxy::{Proxy, ProxyStream},
+        HttpRequest,
+    },
+    futures_util::{future::BoxFuture, stream::FusedStream, Sink, Stream},
+    tokio::{io::DuplexStream, sync::mpsc},
+    tokio_tungstenite::{
+        connect_async,
+        tungstenite::{protocol::Role, Message},
+        WebSocketStream,
+    },
+};
+
+/// Default buffer size of the in-memory duplex connections, in bytes.
+pub const DEFAULT_MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Websocket frame stream used by the [`ClientStream`][super::ClientStream].
+///
+/// Implemented for any [`Sink`] and [`FusedStream`] of the websocket
+/// [`Message`]s, such as [`WebSocketStream`] over an arbitrary IO stream.
+pub trait Socket:
+    Stream<Item = Result<Message, RawTransportError>>
+    + Sink<Message, Error = RawTransportError>
+    + FusedStream
+    + Send
+    + Unpin
+    + 'static
+{
+}
+
+impl<T> Socket for T where
+    T: Stream<Item = Result<Message, RawTransportError>>
+        + Sink<Message, Error = RawTransportError>
+        + FusedStream
+        + Send
+        + Unpin
+        + 'static
+{
+}
+
+pub type BoxSocket = Box<dyn Socket>;
+
+/// Connector opening the websocket connections to the Relay.
+///
+/// The default [`TungsteniteTransport`] connects over TCP (with TLS, if
+/// required by the URL). Custom implementations can be used to provide custom
+/// DNS resolution, TCP connectors, or in-process connections (see
+/// [`MemoryTransport`]).
+///
+/// The [`Proxy`] configured in the [`ConnectionOptions`] is passed in the
+/// request extensions, and can be used with [`Proxy::connect()`].
+///
+/// [`ConnectionOptions`]: crate::ConnectionOptions
+pub trait Transport: std::fmt::Debug + Send + Sync + 'static {
+    /// Opens a new websocket connection using the provided HTTP request.
+    fn connect(
+        &self,
+        request: HttpRequest<()>,
+    ) -> BoxFuture<'static, Result<BoxSocket, WebsocketClientError>>;
+}
+
+/// The default [`Transport`] connecting over TCP using `tokio-tungstenite`.
+///
+/// Tunnels the connections through the [`Proxy`], unless the Relay host is
+/// excluded by its [`NoProxy`][crate::proxy::NoProxy] rules.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TungsteniteTransport;
+
+impl Transport for TungsteniteTransport {
+    fn connect(
+        &self,
+        request: HttpRequest<()>,
+    ) -> BoxFuture<'static, Result<BoxSocket, WebsocketClientError>> {
+        Box::pin(async move {
+            if let Some((proxy, host, port)) = proxy_target(&request) {
+                let stream = proxy.connect(&host, port).await?;
+
+                return handshake(request, stream)
+                    .await
+                    .map_err(Box::new)
+                    .map_err(WebsocketClientError::ConnectionFailed);
+            }
+
+            let (socket, _) = connect_async(request)
+                .await
+                .map_err(Box::new)
+                .map_err(WebsocketClientError::ConnectionFailed)?;
+
+            Ok(Box::new(socket) as BoxSocket)
+        })
+    }
+}
+
+/// Returns the proxy to connect through, along with the target host and port,
+/// unless the request has no proxy or the host is excluded.
+fn proxy_target(request: &HttpRequest<()>) -> Option<(Proxy, String, u16)> {
+    let proxy = request.extensions().get::<Proxy>()?;
+    let uri = request.uri();
+    let host = uri.host()?;
+
+    if proxy.bypasses(host) {
+        return None;
+    }
+
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("wss") => 443,
+        _ => 80,
+    });
+
+    Some((proxy.clone(), host.to_owned(), port))
+}
+
+/// Performs the websocket handshake over the tunneled connection, upgrading it
+/// to TLS if required by the URL.
+async fn handshake(
+    request: HttpRequest<()>,
+    stream: ProxyStream,
+) -> Result<BoxSocket, RawTransportError> {
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    let (socket, _) = tokio_tungstenite::client_async_tls(request, stream).await?;
+
+    #[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+    let (socket, _) = tokio_tungstenite::client_async(request, stream).await?;
+
+    Ok(Box::new(socket))
+}
+
+/// Server side of an in-memory connection produced by the [`MemoryTransport`].
+pub type MemorySocket = WebSocketStream<DuplexStream>;
+
+/// In-memory [`Transport`] that connects to a [`MemoryListener`] within the
+/// same process, without opening any sockets.
+///
+/// The connections use the regular websocket framing over an in-memory duplex
+/// pipe, skipping the HTTP handshake.
+#[derive(Debug, Clone)]
+pub struct MemoryTransport {
+    tx: mpsc::UnboundedSender<(HttpRequest<()>, MemorySocket)>,
+    buffer_size: usize,
+}
+
+impl MemoryTransport {
+    /// Creates a new transport, returning it along with the listener accepting
+    /// its connections.
+    pub fn new() -> (Self, MemoryListener) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let transport = Self {
+            tx,
+            buffer_size: DEFAULT_MEMORY_BUFFER_SIZE,
+        };
+
+        (transport, MemoryListener { rx })
+    }
+
+    /// Sets the buffer size of the duplex connections, in bytes.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn connect(
+        &self,
+        request: HttpRequest<()>,
+    ) -> BoxFuture<'static, Result<BoxSocket, WebsocketClientError>> {
+        let tx = self.tx.clone();
+        let buffer_size = self.buffer_size;
+
+        Box::pin(async move {
+            let (client, server) = tokio::io::duplex(buffer_size);
+            let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+            let server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+
+            tx.send((request, server)).map_err(|_| {
+                WebsocketClientError::ConnectionFailed(Box::new(
+                    RawTransportError::ConnectionClosed,
+                ))
+            })?;
+
+            Ok(Box::new(client) as BoxSocket)
+        })
+    }
+}
+
+/// Accepts the connections opened by the [`MemoryTransport`].
+///
+/// Dropping the listener makes the subsequent connection attempts fail.
+#[derive(Debug)]
+pub struct MemoryListener {
+    rx: mpsc::UnboundedReceiver<(HttpRequest<()>, MemorySocket)>,
+}
+
+impl MemoryListener {
+    /// Waits for the next connection, returning the HTTP request it was opened
+    /// with, and the server side of the connection. Returns `None` if all of
+    /// the transport handles have been dropped.
+    pub async fn accept(&mut self) -> Option<(HttpRequest<()>, MemorySocket)> {
+        self.rx.recv().await
+    }
+}
@@ -1103,6 +1103,30 @@ pub enum Params {
     Subscription(Subscription),
 }
 
+impl Params {
+    /// Returns the JSON-RPC method name of the request.
+    pub fn method(&self) -> &'static str {
+        match self {
+            Self::CreateTopic(_) => "wc_createTopic",
+            Self::ProposeSession(_) => "wc_proposeSession",
+            Self::ApproveSession(_) => "wc_approveSession",
+            Self::Subscribe(_) => "irn_subscribe",
+            Self::SubscribeBlocking(_) => "irn_subscribeBlocking",
+            Self::Unsubscribe(_) => "irn_unsubscribe",
+            Self::FetchMessages(_) => "irn_fetchMessages",
+            Self::BatchSubscribe(_) => "irn_batchSubscribe",
+            Self::BatchSubscribeBlocking(_) => "irn_batchSubscribeBlocking",
+            Self::BatchUnsubscribe(_) => "irn_batchUnsubscribe",
+            Self::BatchFetchMessages(_) => "irn_batchFetchMessages",
+            Self::Publish(_) => "irn_publish",
+            Self::BatchReceiveMessages(_) => "irn_batchReceive",
+            Self::WatchRegister(_) => "irn_watchRegister",
+            Self::WatchUnregister(_) => "irn_watchUnregister",
+            Self::Subscription(_) => "irn_subscription",
+        }
+    }
+}
+
 /// Data structure representing a JSON RPC request.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Deref, DerefMut)]
 pub struct Request {
@@ -117,6 +117,15 @@ pub enum Error<T> {
 
     #[error("Too many requests")]
     TooManyRequests,
+
+    /// Error that couldn't be recognized, e.g. one introduced by a newer Relay
+    /// version. Preserves the raw error data.
+    #[error("Unknown error {code}: {message}")]
+    Unknown {
+        code: i32,
+        message: String,
+        data: Option<String>,
+    },
 }
 
 impl<T: ServiceError> Error<T> {
@@ -127,18 +136,68 @@ impl<T: ServiceError> Error<T> {
             Self::Payload(_) => CODE_PAYLOAD,
             Self::Handler(_) => CODE_HANDLER,
             Self::Internal(_) => CODE_INTERNAL,
+            Self::Unknown { code, .. } => *code,
         }
     }
 
+    /// Returns the string tag of the error. The [`Error::Unknown`] errors
+    /// return `"Unknown"`, see [`Error::raw_tag()`] for the tag received from
+    /// the Relay.
     pub fn tag(&self) -> &'static str {
         match &self {
             Self::Auth(err) => err.tag(),
             Self::Payload(err) => err.tag(),
             Self::Handler(err) => err.tag(),
             Self::Internal(err) => err.tag(),
-            Self::TooManyRequests => self.into(),
+            Self::TooManyRequests | Self::Unknown { .. } => self.into(),
+        }
+    }
+
+    /// Returns the tag received from the Relay for the [`Error::Unknown`]
+    /// errors, if any.
+    pub fn raw_tag(&self) -> Option<&str> {
+        match self {
+            Self::Unknown { data, .. } => data.as_deref(),
+            _ => None,
         }
     }
+
+    /// Parses the error data, preserving the unrecognized errors as
+    /// [`Error::Unknown`] instead of failing.
+    pub fn from_error_data(err: ErrorData) -> Self {
+        parse_error(&err).unwrap_or_else(|_| Self::Unknown {
+            code: err.code,
+            message: err.message,
+            data: err.data,
+        })
+    }
+}
+
+impl<T: ServiceError> Error<T> {
+    /// Returns `true` if the request is likely to succeed if repeated later.
+    /// See [`is_retryable()`](fn@is_retryable).
+    pub fn is_retryable(&self) -> bool {
+        is_retryable(self.code(), Some(self.raw_tag().unwrap_or(self.tag())))
+    }
+
+    /// Returns `true` if the request was rejected due to the failed
+    /// authentication.
+    pub fn is_auth_failure(&self) -> bool {
+        self.code() == CODE_AUTH
+    }
+
+    /// Returns `true` if the request was rejected as malformed.
+    pub fn is_payload_invalid(&self) -> bool {
+        self.code() == CODE_PAYLOAD
+    }
+}
+
+/// Returns `true` if the request rejected with the error code and tag is likely
+/// to succeed if repeated later, i.e. it was rate limited, or failed due to a
+/// storage error.
+pub fn is_retryable(code: i32, tag: Option<&str>) -> bool {
+    code == CODE_TOO_MANY_REQUESTS
+        || (code == CODE_INTERNAL && tag == Some(InternalError::StorageError.tag()))
 }
 
 pub const CODE_AUTH: i32 = 3000;
@@ -155,21 +214,25 @@ impl<T: ServiceError> TryFrom<ErrorData> for Error<T> {
     type Error = InvalidErrorData;
 
     fn try_from(err: ErrorData) -> Result<Self, Self::Error> {
-        let tag = &err.data;
-
-        let err = match err.code {
-            CODE_AUTH => Error::Auth(try_parse_error(tag)?),
-            CODE_TOO_MANY_REQUESTS => Error::TooManyRequests,
-            CODE_PAYLOAD => Error::Payload(try_parse_error(tag)?),
-            CODE_HANDLER => Error::Handler(try_parse_error(tag)?),
-            CODE_INTERNAL => Error::Internal(try_parse_error(tag)?),
-            _ => return Err(InvalidErrorData),
-        };
-
-        Ok(err)
+        parse_error(&err)
     }
 }
 
+fn parse_error<T: ServiceError>(err: &ErrorData) -> Result<Error<T>, InvalidErrorData> {
+    let tag = &err.data;
+
+    let err = match err.code {
+        CODE_AUTH => Error::Auth(try_parse_error(tag)?),
+        CODE_TOO_MANY_REQUESTS => Error::TooManyRequests,
+        CODE_PAYLOAD => Error::Payload(try_parse_error(tag)?),
+        CODE_HANDLER => Error::Handler(try_parse_error(tag)?),
+        CODE_INTERNAL => Error::Internal(try_parse_error(tag)?),
+        _ => return Err(InvalidErrorData),
+    };
+
+    Ok(err)
+}
+
 #[inline]
 fn try_parse_error<T: ServiceError>(tag: &Option<String>) -> Result<T, InvalidErrorData> {
     tag.as_deref().ok_or(InvalidErrorData).map(T::from_tag)?
@@ -177,6 +240,19 @@ fn try_parse_error<T: ServiceError>(tag: &Option<String>) -> Result<T, InvalidEr
 
 impl<T: ServiceError> From<Error<T>> for ErrorData {
     fn from(err: Error<T>) -> Self {
+        if let Error::Unknown {
+            code,
+            message,
+            data,
+        } = err
+        {
+            return Self {
+                code,
+                message,
+                data,
+            };
+        }
+
         Self {
             code: err.code(),
             message: err.to_string(),
@@ -274,6 +274,34 @@ fn batch_receive() {
     assert_eq!(&payload, &deserialized)
 }
 
+#[test]
+fn method_names() {
+    let topic = Topic::from("c4163cf65859106b3f5435fc296e7765411178ed452d1c30337a6230138c9840");
+
+    let params = [
+        Params::Subscribe(Subscribe {
+            topic: topic.clone(),
+        }),
+        Params::BatchSubscribe(BatchSubscribe {
+            topics: vec![topic.clone()],
+        }),
+        Params::BatchReceiveMessages(BatchReceiveMessages {
+            receipts: vec![Receipt {
+                topic,
+                message_id: MessageId::new(123),
+            }],
+        }),
+    ];
+
+    for params in params {
+        let serialized = serde_json::to_value(&params).unwrap();
+        assert_eq!(
+            serialized.get("method").and_then(|method| method.as_str()),
+            Some(params.method())
+        );
+    }
+}
+
 #[test]
 fn watch_register() {
     let params: WatchRegister = WatchRegister {
@@ -772,6 +800,34 @@ fn validation() {
     assert_eq!(request.validate(), Err(PayloadError::InvalidTopic));
 }
 
+#[test]
+fn unknown_error() {
+    let data = ErrorData {
+        code: CODE_HANDLER,
+        message: "New handler error".into(),
+        data: Some("NewHandlerError".into()),
+    };
+
+    assert!(Error::<PublishError>::try_from(data.clone()).is_err());
+
+    let err = Error::<PublishError>::from_error_data(data.clone());
+    assert_eq!(err, Error::Unknown {
+        code: CODE_HANDLER,
+        message: "New handler error".into(),
+        data: Some("NewHandlerError".into()),
+    });
+    assert_eq!(err.code(), CODE_HANDLER);
+    assert_eq!(err.tag(), "Unknown");
+    assert_eq!(err.raw_tag(), Some("NewHandlerError"));
+    assert_eq!(ErrorData::from(err), data);
+
+    // Known errors are parsed as usual.
+    assert_eq!(
+        Error::<PublishError>::from_error_data(Error::<PublishError>::TooManyRequests.into()),
+        Error::TooManyRequests
+    );
+}
+
 #[test]
 fn error_tags() {
     // Validate hardcoded string tags, so that we don't accidentally break